@@ -0,0 +1,164 @@
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use super::{command::execute_commande, filesystem::check_dir_exist_or_create};
+
+/// A single archived build in the artifact store
+pub struct CacheEntry {
+    pub key: String,
+    pub path: String,
+    pub size: u64,
+    pub age: Duration,
+}
+
+/// FNV-1a, stable across builds and platforms unlike the std hasher
+pub fn hash_str(content: &str) -> String {
+    return hash_bytes(content.as_bytes());
+}
+
+pub fn hash_bytes(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return format!("{:016x}", hash);
+}
+
+pub fn cache_key(commit: &str, build_config: &str) -> String {
+    return format!("{}-{}", commit.trim(), hash_str(build_config));
+}
+
+fn archive_path(cache_dir: &str, key: &str) -> String {
+    return format!("{}/{}.tar.gz", cache_dir, key);
+}
+
+pub fn has_artifacts(cache_dir: &str, key: &str) -> bool {
+    return Path::new(&archive_path(cache_dir, key)).is_file();
+}
+
+/// Archives the given files (relative to `repo_dir`) under `key`
+pub fn store_artifacts(
+    cache_dir: &str,
+    key: &str,
+    repo_dir: &str,
+    paths: &[String],
+) -> Result<String, String> {
+    check_dir_exist_or_create(&format!("{}/example", cache_dir));
+
+    let archive = archive_path(cache_dir, key);
+    let tmp_archive = format!("{}.part", &archive);
+
+    let file_list = format!("{}.list", &archive);
+    if let Err(err) = fs::write(&file_list, paths.join("\n")) {
+        return Err(err.to_string());
+    }
+
+    // write next to the final archive first so a failed run never leaves a truncated entry
+    let res = execute_commande(&format!(
+        "tar -czf {} -C {} -T {}",
+        &tmp_archive, repo_dir, &file_list
+    ));
+    let _ = fs::remove_file(&file_list);
+
+    if let Err(err) = res {
+        let _ = fs::remove_file(&tmp_archive);
+        return Err(err);
+    }
+
+    match fs::rename(&tmp_archive, &archive) {
+        Ok(_) => return Ok(archive),
+        Err(err) => return Err(err.to_string()),
+    }
+}
+
+/// Extracts the archive stored under `key` into `repo_dir`
+pub fn restore_artifacts(cache_dir: &str, key: &str, repo_dir: &str) -> Result<String, String> {
+    let archive = archive_path(cache_dir, key);
+    return execute_commande(&format!("tar -xzf {} -C {}", &archive, repo_dir));
+}
+
+pub fn list_entries(cache_dir: &str) -> Result<Vec<CacheEntry>, String> {
+    let dir_content = match fs::read_dir(cache_dir) {
+        Ok(content) => content,
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let mut entries = Vec::<CacheEntry>::new();
+
+    for entry in dir_content.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        let key = match file_name.strip_suffix(".tar.gz") {
+            Some(key) => key.to_string(),
+            None => continue,
+        };
+
+        let metadata = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or_default();
+
+        entries.push(CacheEntry {
+            key,
+            path: path.to_string_lossy().to_string(),
+            size: metadata.len(),
+            age,
+        });
+    }
+
+    // newest first
+    entries.sort_by_key(|entry| entry.age);
+
+    return Ok(entries);
+}
+
+/// Removes entries older than `max_age` and then the oldest entries until the store
+/// fits in `max_size` bytes, returning the removed entries
+pub fn prune_entries(
+    cache_dir: &str,
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+) -> Result<Vec<CacheEntry>, String> {
+    let entries = list_entries(cache_dir)?;
+
+    let mut removed = Vec::<CacheEntry>::new();
+    let mut kept_size: u64 = 0;
+
+    for entry in entries {
+        let too_old = max_age.is_some_and(|max| entry.age > max);
+        let too_big = max_size.is_some_and(|max| kept_size + entry.size > max);
+
+        if too_old || too_big {
+            match fs::remove_file(&entry.path) {
+                Ok(_) => removed.push(entry),
+                Err(err) => return Err(err.to_string()),
+            }
+            continue;
+        }
+
+        kept_size += entry.size;
+    }
+
+    return Ok(removed);
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+        s => format!("{}d {}h", s / 86400, (s % 86400) / 3600),
+    }
+}
@@ -1,10 +1,15 @@
-use crate::utils::structs::SysInfo;
-use log::{error, info};
+use crate::utils::structs::{StepConfig, StepTag};
+use log::{Level, log};
 use std::{
-    io::{self},
-    process::Command,
+    io::{self, BufRead, BufReader},
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{Command, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
-use sysinfo::System;
+
+const READER_GRACE: Duration = Duration::from_secs(2);
 
 pub fn prompt_user(str: &str) -> Result<String, String> {
     // Prompt the user for input
@@ -19,7 +24,7 @@ pub fn prompt_user(str: &str) -> Result<String, String> {
         }
     }
 
-    let input = String::from(input.trim().to_lowercase());
+    let input = input.trim().to_lowercase();
     return Ok(input);
 }
 
@@ -36,32 +41,163 @@ pub fn execute_commande(commande: &str) -> Result<String, String> {
     }
 }
 
-pub fn get_sys_info(pid_str: &str) -> Result<SysInfo, bool> {
-    let pid: usize = match pid_str.parse() {
-        Ok(num) => num,
-        Err(err) => {
-            println!("error : {}", err);
-            return Err(false);
+pub fn kill_process_group(pgid: u32) -> Result<String, String> {
+    execute_commande(&format!("kill -KILL -{}", pgid))
+}
+
+/// Logs `message` with the stage, step and host of `tag` as fields, and the output stream
+/// it was read from when it comes from the step itself
+pub fn log_step(level: Level, tag: &StepTag, stream: Option<&str>, message: &str) -> () {
+    log!(
+        level,
+        stage = tag.stage.as_str(),
+        step = tag.step,
+        step_name = tag.name.as_deref(),
+        host = tag.host.as_deref(),
+        stream = stream;
+        "{}",
+        message
+    );
+}
+
+/// Runs a build step, streaming its output to the log with every line tagged by `tag`
+pub fn execute_step(step: &StepConfig, repo_dir: &str, tag: &StepTag) -> Result<(), String> {
+    let shell = step.shell.clone().unwrap_or("sh".to_string());
+
+    let cwd = match &step.cwd {
+        Some(dir) if Path::new(dir).is_absolute() => dir.clone(),
+        Some(dir) => format!("{}/{}", repo_dir, dir),
+        None => repo_dir.to_string(),
+    };
+
+    let mut command = Command::new(&shell);
+    command
+        .arg("-c")
+        .arg(&step.run)
+        .current_dir(&cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // own process group so a timeout can take down every child of the step
+        .process_group(0);
+
+    if let Some(env) = &step.env {
+        command.envs(env);
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => return Err(format!("failed to start `{}` : {}", &shell, err)),
+    };
+
+    // stream both pipes line by line into the log while the step runs
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let out_tag = tag.clone();
+    let err_tag = tag.clone();
+    let out_reader = thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            log_step(Level::Info, &out_tag, Some("stdout"), &line);
+        }
+    });
+    let err_reader = thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            log_step(Level::Warn, &err_tag, Some("stderr"), &line);
+        }
+    });
+
+    let started = Instant::now();
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(err) => return Err(err.to_string()),
+        }
+
+        if let Some(timeout) = step.timeout
+            && started.elapsed() >= Duration::from_secs(timeout)
+        {
+            unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+            let _ = child.wait();
+            join_readers(tag, vec![out_reader, err_reader]);
+            return Err(format!(
+                "timed out after {}s, process group {} killed",
+                timeout,
+                child.id()
+            ));
         }
+
+        thread::sleep(Duration::from_millis(100));
     };
-    let mut system = System::new_all();
-    system.refresh_all();
-
-    match system.process(pid.into()) {
-        Some(process) => {
-            let mem: f32 = process.memory() as f32 / 1_000_000 as f32;
-            return Ok(SysInfo {
-                name: format!("{:?}", process.name()),
-                cpu_usage: format!("{}%", process.cpu_usage()),
-                memory: format!("{:.2}mb", mem),
-                status: format!("{}", process.status()),
-            });
+
+    join_readers(tag, vec![out_reader, err_reader]);
+
+    if !status.success() {
+        return Err(format!("exited with {}", status));
+    }
+
+    return Ok(());
+}
+
+/// Waits up to READER_GRACE for the output readers of a step to reach the end of their pipe,
+/// a process the step left in the background may hold the pipe open for as long as it runs
+fn join_readers(tag: &StepTag, readers: Vec<JoinHandle<()>>) -> () {
+    let deadline = Instant::now() + READER_GRACE;
+
+    while readers.iter().any(|reader| !reader.is_finished()) {
+        if Instant::now() >= deadline {
+            log_step(
+                Level::Warn,
+                tag,
+                None,
+                "a background process of the step still holds its output open",
+            );
+            return;
         }
-        None => Err(false),
+        thread::sleep(Duration::from_millis(20));
     }
 
-    // println!("Name: {}", process.name());
-    // println!("CPU usage: {}%", process.cpu_usage());
-    // println!("Memory: {} KB", process.memory());
-    // println!("Status: {:?}", process.status());
+    for reader in readers {
+        let _ = reader.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(run: &str, timeout: Option<u64>) -> StepConfig {
+        return StepConfig {
+            run: run.to_string(),
+            timeout,
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn step_returns_while_a_background_process_holds_its_output() {
+        let started = Instant::now();
+        let result = execute_step(
+            &step("sleep 30 & echo done", None),
+            "/tmp",
+            &StepTag::default(),
+        );
+
+        assert!(result.is_ok());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn timed_out_step_returns_while_a_background_process_holds_its_output() {
+        let started = Instant::now();
+        let result = execute_step(
+            &step("setsid sleep 30 & sleep 30", Some(1)),
+            "/tmp",
+            &StepTag::default(),
+        );
+
+        assert!(result.unwrap_err().starts_with("timed out after 1s"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
 }
@@ -8,7 +8,7 @@ use std::{
 
 pub fn write_to_file_ut(file_path: &str, content: &str) -> Result<bool, String> {
     // Create all directories in the path if they don't exist
-    check_dir_exist_or_create(&file_path);
+    check_dir_exist_or_create(file_path);
 
     // Open or create the file
     let mut file = match OpenOptions::new().create(true).append(true).open(file_path) {
@@ -33,15 +33,15 @@ pub fn read_from_file_ut(file_path: &str) -> Result<String, String> {
 }
 
 pub fn check_dir_exist_or_create(file_path: &str) -> () {
-    let tmp_path = format!("{}", file_path);
+    let tmp_path = file_path.to_string();
     // Convert the file path to a Path
     let path = Path::new::<String>(&tmp_path);
 
     // Create all directories in the path if they don't exist
-    if let Some(parent) = path.parent() {
-        if let Err(err) = create_dir_all(parent) {
-            println!("Failed to create directories: {}", err);
-        }
+    if let Some(parent) = path.parent()
+        && let Err(err) = create_dir_all(parent)
+    {
+        println!("Failed to create directories: {}", err);
     }
 }
 pub fn load_file_parsed<T>(config_path: &str) -> Result<T, String>
@@ -70,7 +70,7 @@ pub fn list_dir_contents(path: &str) -> Result<Vec<String>, bool> {
     let dir_content = match fs::read_dir(path) {
         Ok(content) => content,
         Err(err) => {
-            print!("{}", err.to_string());
+            print!("{}", err);
             return Err(false);
         }
     };
@@ -81,7 +81,7 @@ pub fn list_dir_contents(path: &str) -> Result<Vec<String>, bool> {
         let curr_entry = match entry {
             Ok(curr_entry) => curr_entry,
             Err(err) => {
-                print!("{}", err.to_string());
+                print!("{}", err);
                 continue;
             }
         };
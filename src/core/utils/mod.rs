@@ -1,4 +1,11 @@
+pub mod cache;
 pub mod command;
 pub mod daemon;
 pub mod filesystem;
 pub mod git;
+pub mod permissions;
+pub mod remote;
+pub mod rotate;
+pub mod sampler;
+pub mod sync;
+pub mod template;
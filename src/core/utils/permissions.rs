@@ -0,0 +1,214 @@
+use std::{
+    collections::BTreeSet,
+    ffi::CString,
+    fs, mem,
+    os::unix::fs::{PermissionsExt, lchown},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use crate::utils::structs::FromTo;
+
+use super::sync::{SyncAction, SyncPlan};
+
+/// Owner and modes of a `mouve` entry, resolved to numeric ids
+#[derive(Debug, Default)]
+pub struct Ownership {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+}
+
+impl Ownership {
+    pub fn is_empty(&self) -> bool {
+        return self.uid.is_none()
+            && self.gid.is_none()
+            && self.file_mode.is_none()
+            && self.dir_mode.is_none();
+    }
+}
+
+/// Looks the name up in the user or group database, a numeric id is taken as is
+fn resolve_id(kind: &str, name: &str) -> Result<u32, String> {
+    if let Ok(id) = name.parse::<u32>() {
+        return Ok(id);
+    }
+
+    let missing = format!("{} `{}` does not exist", kind, name);
+    let c_name = CString::new(name).map_err(|_| missing.clone())?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+
+    loop {
+        let (id, err) = match kind {
+            "user" => unsafe {
+                let mut pwd: libc::passwd = mem::zeroed();
+                let mut result = ptr::null_mut();
+                let err = libc::getpwnam_r(
+                    c_name.as_ptr(),
+                    &mut pwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                );
+                ((!result.is_null()).then_some(pwd.pw_uid), err)
+            },
+            _ => unsafe {
+                let mut grp: libc::group = mem::zeroed();
+                let mut result = ptr::null_mut();
+                let err = libc::getgrnam_r(
+                    c_name.as_ptr(),
+                    &mut grp,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                );
+                ((!result.is_null()).then_some(grp.gr_gid), err)
+            },
+        };
+
+        match (id, err) {
+            (Some(id), _) => return Ok(id),
+            // the entry didn't fit, groups with many members need a larger buffer
+            (None, libc::ERANGE) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            _ => return Err(missing),
+        }
+    }
+}
+
+/// Parses an octal mode such as `"0644"` or `"755"`
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode.trim_start_matches("0o"), 8) {
+        Ok(bits) if bits <= 0o7777 => return Ok(bits),
+        _ => return Err(format!("`{}` is not a valid octal file mode", mode)),
+    }
+}
+
+/// Checks that the users, groups and modes of `entry` exist and are valid
+pub fn resolve_ownership(entry: &FromTo) -> Result<Ownership, String> {
+    return resolve_ids(
+        &entry.owner,
+        &entry.group,
+        &entry.file_mode,
+        &entry.dir_mode,
+    );
+}
+
+pub fn resolve_ids(
+    owner: &Option<String>,
+    group: &Option<String>,
+    file_mode: &Option<String>,
+    dir_mode: &Option<String>,
+) -> Result<Ownership, String> {
+    let mut ownership = Ownership::default();
+
+    if let Some(owner) = owner {
+        ownership.uid = Some(resolve_id("user", owner)?);
+    }
+    if let Some(group) = group {
+        ownership.gid = Some(resolve_id("group", group)?);
+    }
+    if let Some(mode) = file_mode {
+        ownership.file_mode = Some(parse_mode(mode)?);
+    }
+    if let Some(mode) = dir_mode {
+        ownership.dir_mode = Some(parse_mode(mode)?);
+    }
+
+    return Ok(ownership);
+}
+
+/// Applies the owner and file mode to a single file
+pub fn apply_file_ownership(path: &Path, ownership: &Ownership) -> Result<(), String> {
+    if let Err(err) = lchown(path, ownership.uid, ownership.gid) {
+        return Err(format!("chown {} : {}", path.display(), err));
+    }
+    if let Some(mode) = ownership.file_mode
+        && let Err(err) = fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    {
+        return Err(format!("chmod {} : {}", path.display(), err));
+    }
+    return Ok(());
+}
+
+/// Applies the ownership to every deployed file of `plan` and to the directories
+/// between them and the destination root
+pub fn apply_ownership(plan: &SyncPlan, ownership: &Ownership) -> Result<(), String> {
+    if ownership.is_empty() {
+        return Ok(());
+    }
+
+    let mut dirs = BTreeSet::<PathBuf>::new();
+    dirs.insert(plan.root.clone());
+
+    for item in &plan.items {
+        if item.action == SyncAction::Delete {
+            continue;
+        }
+
+        let mut parent = item.dst.parent();
+        while let Some(dir) = parent {
+            if !dir.starts_with(&plan.root) {
+                break;
+            }
+            dirs.insert(dir.to_path_buf());
+            parent = dir.parent();
+        }
+
+        let meta = match fs::symlink_metadata(&item.dst) {
+            Ok(meta) => meta,
+            Err(err) => return Err(format!("{} : {}", item.dst.display(), err)),
+        };
+
+        if let Err(err) = lchown(&item.dst, ownership.uid, ownership.gid) {
+            return Err(format!("chown {} : {}", item.dst.display(), err));
+        }
+
+        // symlinks carry no mode of their own
+        if meta.file_type().is_symlink() {
+            continue;
+        }
+
+        if let Some(mode) = ownership.file_mode
+            && let Err(err) = fs::set_permissions(&item.dst, fs::Permissions::from_mode(mode))
+        {
+            return Err(format!("chmod {} : {}", item.dst.display(), err));
+        }
+    }
+
+    for dir in dirs {
+        if let Err(err) = lchown(&dir, ownership.uid, ownership.gid) {
+            return Err(format!("chown {} : {}", dir.display(), err));
+        }
+        if let Some(mode) = ownership.dir_mode
+            && let Err(err) = fs::set_permissions(&dir, fs::Permissions::from_mode(mode))
+        {
+            return Err(format!("chmod {} : {}", dir.display(), err));
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_id_reads_the_user_and_group_databases() {
+        assert_eq!(resolve_id("user", "root"), Ok(0));
+        assert_eq!(resolve_id("group", "root"), Ok(0));
+        assert_eq!(resolve_id("user", "1234"), Ok(1234));
+    }
+
+    #[test]
+    fn resolve_id_takes_the_name_literally() {
+        let marker = std::env::temp_dir().join(format!("fast_flow-ids-{}", std::process::id()));
+        let name = format!("root; touch {}", marker.display());
+
+        assert!(resolve_id("user", &name).is_err());
+        assert!(resolve_id("group", &name).is_err());
+        assert!(resolve_id("user", "ro\0ot").is_err());
+        assert!(!marker.exists());
+    }
+}
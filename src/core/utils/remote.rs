@@ -0,0 +1,389 @@
+use std::{collections::HashMap, fs};
+
+use crate::utils::structs::{CompareBy, FromTo, SshHost, StepConfig, SyncMode};
+
+use super::{
+    command::execute_commande,
+    sync::{SyncPlan, apply_sync, plan_sync},
+};
+
+/// A `host:path` destination of a `mouve` entry
+#[derive(Debug, Clone)]
+pub struct RemoteTarget {
+    /// Name the host was referenced by, used in logs
+    pub name: String,
+    pub host: SshHost,
+    pub path: String,
+}
+
+/// Wraps `value` in single quotes for `sh`
+pub fn shell_quote(value: &str) -> String {
+    return format!("'{}'", value.replace('\'', "'\\''"));
+}
+
+/// Looks `name` up in the configured hosts, anything else is used as a plain
+/// `[user@]hostname` with the ssh defaults
+pub fn resolve_host(name: &str, hosts: &HashMap<String, SshHost>) -> SshHost {
+    match hosts.get(name) {
+        Some(host) => return host.clone(),
+        None => {
+            return SshHost {
+                host: name.to_string(),
+                ..Default::default()
+            };
+        }
+    }
+}
+
+/// Splits a `host:path` destination, local absolute paths give `None`
+pub fn parse_target(to: &str, hosts: &HashMap<String, SshHost>) -> Option<RemoteTarget> {
+    if to.starts_with('/') {
+        return None;
+    }
+
+    let (name, path) = to.split_once(':')?;
+
+    return Some(RemoteTarget {
+        name: name.to_string(),
+        host: resolve_host(name, hosts),
+        path: path.to_string(),
+    });
+}
+
+/// `ssh` invocation (without the destination) for the host's connection settings
+pub fn ssh_command(host: &SshHost) -> String {
+    let mut command = vec![
+        "ssh".to_string(),
+        "-o BatchMode=yes".to_string(),
+        "-o StrictHostKeyChecking=accept-new".to_string(),
+    ];
+
+    if let Some(port) = host.port {
+        command.push(format!("-p {}", port));
+    }
+    if let Some(identity) = &host.identity_file {
+        command.push(format!("-i {}", shell_quote(identity)));
+    }
+    for option in host.options.clone().unwrap_or_default() {
+        command.push(format!("-o {}", shell_quote(&option)));
+    }
+
+    return command.join(" ");
+}
+
+/// `[user@]hostname` as understood by ssh and rsync
+pub fn ssh_destination(host: &SshHost) -> String {
+    match &host.user {
+        Some(user) => return format!("{}@{}", user, &host.host),
+        None => return host.host.clone(),
+    }
+}
+
+/// Turns a step into the local command running it on `host` over ssh, with the
+/// step's environment exported on the remote side
+pub fn remote_step(step: &StepConfig, host: &SshHost) -> StepConfig {
+    let mut script = String::new();
+
+    let mut env: Vec<(&String, &String)> = step.env.iter().flatten().collect();
+    env.sort();
+    for (key, value) in env {
+        script.push_str(&format!("export {}={}; ", key, shell_quote(value)));
+    }
+
+    if let Some(dir) = &step.cwd {
+        script.push_str(&format!("cd {} && ", shell_quote(dir)));
+    }
+
+    match &step.shell {
+        Some(shell) => script.push_str(&format!("{} -c {}", shell, shell_quote(&step.run))),
+        None => script.push_str(&step.run),
+    }
+
+    return StepConfig {
+        run: format!(
+            "{} {} {}",
+            ssh_command(host),
+            shell_quote(&ssh_destination(host)),
+            shell_quote(&script)
+        ),
+        cwd: None,
+        env: None,
+        shell: None,
+        host: None,
+        ..step.clone()
+    };
+}
+
+/// rsync command pushing the staged files of `entry` to the remote destination,
+/// following the entry's sync mode, comparison and ownership settings
+pub fn rsync_command(
+    stage_dir: &str,
+    entry: &FromTo,
+    target: &RemoteTarget,
+    dry_run: bool,
+) -> String {
+    let mut command = vec!["rsync".to_string(), "-a".to_string()];
+
+    if dry_run {
+        command.push("--dry-run".to_string());
+        command.push("--itemize-changes".to_string());
+    }
+
+    let mode = entry.mode.clone().unwrap_or_default();
+    match mode {
+        SyncMode::Copy => command.push("--ignore-times".to_string()),
+        SyncMode::Sync => {}
+        SyncMode::Mirror => command.push("--delete".to_string()),
+    }
+
+    // copy sends every file whatever they compare to
+    if mode != SyncMode::Copy && entry.compare.clone().unwrap_or_default() == CompareBy::Hash {
+        command.push("--checksum".to_string());
+    }
+
+    // excluded files are never staged, excluding them again protects them from --delete
+    for pattern in entry.exclude.clone().unwrap_or_default() {
+        command.push(format!("--exclude={}", shell_quote(&pattern)));
+    }
+
+    let owner = match (&entry.owner, &entry.group) {
+        (Some(owner), Some(group)) => Some(format!("{}:{}", owner, group)),
+        (Some(owner), None) => Some(owner.clone()),
+        (None, Some(group)) => Some(format!(":{}", group)),
+        (None, None) => None,
+    };
+    if let Some(owner) = owner {
+        command.push(format!("--chown={}", shell_quote(&owner)));
+    }
+
+    let mut modes = Vec::<String>::new();
+    if let Some(mode) = &entry.dir_mode {
+        modes.push(format!("D{}", mode));
+    }
+    if let Some(mode) = &entry.file_mode {
+        modes.push(format!("F{}", mode));
+    }
+    if !modes.is_empty() {
+        command.push(format!("--chmod={}", shell_quote(&modes.join(","))));
+    }
+
+    command.push(format!("-e {}", shell_quote(&ssh_command(&target.host))));
+    // create the destination on the remote side before receiving, a dry run leaves it be
+    if !dry_run {
+        command.push(format!(
+            "--rsync-path={}",
+            shell_quote(&format!("mkdir -p {} && rsync", shell_quote(&target.path)))
+        ));
+    }
+    command.push(format!("{}/", shell_quote(stage_dir)));
+    command.push(format!(
+        "{}:{}/",
+        shell_quote(&ssh_destination(&target.host)),
+        shell_quote(target.path.trim_end_matches('/'))
+    ));
+
+    return command.join(" ");
+}
+
+/// Stages the files of `entry` in `stage_dir` and pushes them to the remote target,
+/// returning the staged files and the rsync output
+pub fn push_to_remote(
+    repo_dir: &str,
+    stage_dir: &str,
+    entry: &FromTo,
+    target: &RemoteTarget,
+    dry_run: bool,
+) -> Result<(SyncPlan, String), String> {
+    let _ = fs::remove_dir_all(stage_dir);
+
+    // same source resolution as a local deploy, staged so rsync sees the final layout
+    let staged = FromTo {
+        to: stage_dir.to_string(),
+        mode: Some(SyncMode::Copy),
+        ..entry.clone()
+    };
+
+    let res = plan_sync(repo_dir, &staged).and_then(|plan| {
+        apply_sync(&plan)?;
+        let output = execute_commande(&rsync_command(stage_dir, entry, target, dry_run))?;
+        Ok((plan, output))
+    });
+
+    let _ = fs::remove_dir_all(stage_dir);
+
+    return res;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    /// Arguments `sh` hands to `command`, one per entry
+    fn argv(command: &str) -> Vec<String> {
+        let (_, args) = command.split_once(' ').unwrap();
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf '%s\\n' {}", args))
+            .output()
+            .unwrap();
+        return String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect();
+    }
+
+    fn web_host() -> SshHost {
+        return SshHost {
+            host: "web.example.com".to_string(),
+            user: Some("deploy".to_string()),
+            port: Some(2222),
+            identity_file: Some("/home/ci/my key".to_string()),
+            options: None,
+        };
+    }
+
+    #[test]
+    fn parse_target_keeps_local_paths_local() {
+        assert!(parse_target("/var/www/app", &HashMap::new()).is_none());
+        assert!(parse_target("relative/path", &HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn parse_target_resolves_configured_hosts() {
+        let hosts = HashMap::from([("web".to_string(), web_host())]);
+
+        let target = parse_target("web:/srv/my app", &hosts).unwrap();
+        assert_eq!(target.name, "web");
+        assert_eq!(target.host.host, "web.example.com");
+        assert_eq!(target.host.port, Some(2222));
+        assert_eq!(target.path, "/srv/my app");
+    }
+
+    #[test]
+    fn parse_target_takes_unknown_hosts_as_is() {
+        let target = parse_target("ops@db1:/srv/app", &HashMap::new()).unwrap();
+        assert_eq!(target.name, "ops@db1");
+        assert_eq!(target.host.host, "ops@db1");
+        assert_eq!(target.host.user, None);
+        assert_eq!(target.path, "/srv/app");
+    }
+
+    #[test]
+    fn ssh_command_applies_the_connection_settings() {
+        let host = SshHost {
+            options: Some(vec!["ProxyJump=bastion host".to_string()]),
+            ..web_host()
+        };
+
+        assert_eq!(
+            argv(&ssh_command(&host)),
+            vec![
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "StrictHostKeyChecking=accept-new",
+                "-p",
+                "2222",
+                "-i",
+                "/home/ci/my key",
+                "-o",
+                "ProxyJump=bastion host",
+            ]
+        );
+    }
+
+    #[test]
+    fn ssh_command_defaults() {
+        let host = SshHost {
+            host: "web".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ssh_command(&host),
+            "ssh -o BatchMode=yes -o StrictHostKeyChecking=accept-new"
+        );
+    }
+
+    #[test]
+    fn rsync_command_quotes_paths_with_spaces() {
+        let target = RemoteTarget {
+            name: "web".to_string(),
+            host: web_host(),
+            path: "/srv/my app/".to_string(),
+        };
+        let entry = FromTo {
+            from: "dist/".to_string(),
+            to: "web:/srv/my app/".to_string(),
+            exclude: Some(vec!["*.log".to_string()]),
+            owner: Some("www-data".to_string()),
+            file_mode: Some("0644".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            argv(&rsync_command("/tmp/stage dir", &entry, &target, false)),
+            vec![
+                "-a",
+                "--ignore-times",
+                "--exclude=*.log",
+                "--chown=www-data",
+                "--chmod=F0644",
+                "-e",
+                "ssh -o BatchMode=yes -o StrictHostKeyChecking=accept-new -p 2222 -i '/home/ci/my key'",
+                "--rsync-path=mkdir -p '/srv/my app/' && rsync",
+                "/tmp/stage dir/",
+                "deploy@web.example.com:/srv/my app/",
+            ]
+        );
+    }
+
+    #[test]
+    fn rsync_command_dry_run_creates_nothing() {
+        let target = RemoteTarget {
+            name: "web".to_string(),
+            host: web_host(),
+            path: "/srv/app".to_string(),
+        };
+
+        let args = argv(&rsync_command(
+            "/tmp/stage",
+            &FromTo::default(),
+            &target,
+            true,
+        ));
+        assert_eq!(args[1..3], ["--dry-run", "--itemize-changes"]);
+        assert!(!args.iter().any(|arg| arg.starts_with("--rsync-path")));
+    }
+
+    #[test]
+    fn rsync_command_follows_the_sync_mode() {
+        let target = RemoteTarget {
+            name: "web".to_string(),
+            host: SshHost {
+                host: "web".to_string(),
+                ..Default::default()
+            },
+            path: "/srv/app".to_string(),
+        };
+        let entry = FromTo {
+            mode: Some(SyncMode::Mirror),
+            compare: Some(CompareBy::Hash),
+            owner: Some("app".to_string()),
+            group: Some("www".to_string()),
+            dir_mode: Some("0755".to_string()),
+            ..Default::default()
+        };
+
+        let args = argv(&rsync_command("/tmp/stage", &entry, &target, false));
+        assert!(args.contains(&"--delete".to_string()));
+        assert!(args.contains(&"--checksum".to_string()));
+        assert!(args.contains(&"--chown=app:www".to_string()));
+        assert!(args.contains(&"--chmod=D0755".to_string()));
+        assert!(!args.contains(&"--ignore-times".to_string()));
+        assert_eq!(args.last().unwrap(), "web:/srv/app/");
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::{AsRawFd, RawFd},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use super::{command::execute_commande, remote::shell_quote};
+
+/// Append-only log file that can be reopened at the same path after a rotation
+#[derive(Clone)]
+pub struct LogFile {
+    path: String,
+    file: Arc<Mutex<File>>,
+}
+
+fn open_append(path: &str) -> io::Result<File> {
+    return OpenOptions::new().create(true).append(true).open(path);
+}
+
+impl LogFile {
+    pub fn open(path: &str) -> io::Result<LogFile> {
+        return Ok(LogFile {
+            path: path.to_string(),
+            file: Arc::new(Mutex::new(open_append(path)?)),
+        });
+    }
+
+    /// Swaps in a fresh handle on `path`, the old one keeps pointing at the rotated file
+    pub fn reopen(&self) -> io::Result<()> {
+        let file = open_append(&self.path)?;
+        *self.file.lock().unwrap() = file;
+        return Ok(());
+    }
+
+    pub fn raw_fd(&self) -> RawFd {
+        return self.file.lock().unwrap().as_raw_fd();
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.file.lock().unwrap().write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.file.lock().unwrap().flush();
+    }
+}
+
+/// Index of a rotated file of `file_name`, `app.log.3` and `app.log.3.gz` are 3
+fn rotation_index(file_name: &str, candidate: &str) -> Option<usize> {
+    let rest = candidate.strip_prefix(file_name)?.strip_prefix('.')?;
+    return rest.strip_suffix(".gz").unwrap_or(rest).parse().ok();
+}
+
+/// Rotated files of `path`, oldest first
+pub fn rotated_files(path: &str) -> Vec<String> {
+    let path = Path::new(path);
+    let (dir, file_name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(file_name)) => (dir, file_name.to_string_lossy().to_string()),
+        _ => return Vec::new(),
+    };
+
+    let mut rotated: Vec<(usize, String)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let idx = rotation_index(&file_name, &name)?;
+            Some((idx, entry.path().to_string_lossy().to_string()))
+        })
+        .collect();
+    rotated.sort_by_key(|(idx, _)| std::cmp::Reverse(*idx));
+
+    return rotated.into_iter().map(|(_, path)| path).collect();
+}
+
+/// Whether `path` reached `max_size` bytes, or was last rotated more than `max_age` ago
+pub fn rotation_due(path: &str, max_size: Option<u64>, max_age: Option<Duration>) -> bool {
+    let meta = match fs::metadata(path) {
+        Ok(meta) if meta.len() != 0 => meta,
+        _ => return false,
+    };
+
+    if max_size.is_some_and(|max_size| meta.len() >= max_size) {
+        return true;
+    }
+
+    let max_age = match max_age {
+        Some(max_age) => max_age,
+        None => return false,
+    };
+
+    // the newest rotated file was written when the current one started
+    let started = match rotated_files(path).last() {
+        Some(newest) => fs::metadata(newest).and_then(|meta| meta.modified()),
+        None => meta.created(),
+    };
+
+    return started
+        .ok()
+        .and_then(|started| SystemTime::now().duration_since(started).ok())
+        .is_some_and(|age| age >= max_age);
+}
+
+/// Moves `path` to `path.1`, shifting older files up and dropping the ones past `keep`.
+/// With `copy_truncate` the log is copied and emptied instead, for writers that can't
+/// reopen it; they must have it open in append mode
+pub fn rotate(path: &str, keep: usize, compress: bool, copy_truncate: bool) -> Result<(), String> {
+    let numbered = |idx: usize, gz: bool| match gz {
+        true => format!("{}.{}.gz", path, idx),
+        false => format!("{}.{}", path, idx),
+    };
+
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    for rotated in rotated_files(path) {
+        let rotated_name = Path::new(&rotated)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let idx = rotation_index(&file_name, &rotated_name).unwrap_or(0);
+        let gz = rotated.ends_with(".gz");
+
+        // oldest first, so every rename lands on a free slot
+        let res = match idx >= keep {
+            true => fs::remove_file(&rotated),
+            false => fs::rename(&rotated, numbered(idx + 1, gz)),
+        };
+        if let Err(err) = res {
+            return Err(format!("{} : {}", &rotated, err));
+        }
+    }
+
+    if keep == 0 {
+        return OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(0))
+            .map_err(|err| format!("{} : {}", path, err));
+    }
+
+    let first = numbered(1, false);
+    let res = match copy_truncate {
+        true => fs::copy(path, &first).and_then(|_| {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(0))
+        }),
+        false => fs::rename(path, &first),
+    };
+    if let Err(err) = res {
+        return Err(format!("{} : {}", path, err));
+    }
+
+    if compress {
+        execute_commande(&format!("gzip -f {}", shell_quote(&first)))?;
+    }
+
+    return Ok(());
+}
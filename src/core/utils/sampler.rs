@@ -0,0 +1,196 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use sysinfo::{MINIMUM_CPU_UPDATE_INTERVAL, Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::utils::structs::ProcessUsage;
+
+/// Time the cpu usage of a one-off sample is measured over
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Parent and number of threads of `pid`, from `/proc/<pid>/stat`
+fn read_stat(pid: u32) -> Option<(u32, u64)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // the command name may hold spaces and parentheses, the fields start after its last `)`
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let parent = fields.get(1)?.parse().ok()?;
+    let threads = fields.get(17)?.parse().ok()?;
+
+    return Some((parent, threads));
+}
+
+/// Children of `pid`, from the `children` file of each of its threads
+fn read_children(pid: u32) -> Vec<u32> {
+    let mut children = Vec::<u32>::new();
+
+    for task in fs::read_dir(format!("/proc/{}/task", pid))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let content = fs::read_to_string(task.path().join("children")).unwrap_or_default();
+        children.extend(
+            content
+                .split_whitespace()
+                .filter_map(|child| child.parse::<u32>().ok()),
+        );
+    }
+
+    return children;
+}
+
+/// Parent and number of threads of every process, for kernels without the `children`
+/// files. Only the processes are listed there, their threads are not
+fn full_process_table() -> HashMap<u32, (u32, u64)> {
+    let mut table = HashMap::<u32, (u32, u64)>::new();
+
+    for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+            Ok(pid) => pid,
+            Err(_) => continue,
+        };
+        if let Some(stat) = read_stat(pid) {
+            table.insert(pid, stat);
+        }
+    }
+
+    return table;
+}
+
+/// Parent and number of threads of the processes of the trees of `roots`, walked down from
+/// them through `/proc/<pid>/task/*/children`. The whole of `/proc` is read instead when
+/// the kernel was built without those files
+fn process_table(roots: &[u32]) -> HashMap<u32, (u32, u64)> {
+    let own_children = format!("/proc/self/task/{}/children", std::process::id());
+    if !Path::new(&own_children).exists() {
+        return full_process_table();
+    }
+
+    let mut table = HashMap::<u32, (u32, u64)>::new();
+    let mut queue: Vec<u32> = roots.to_vec();
+
+    while let Some(pid) = queue.pop() {
+        if table.contains_key(&pid) {
+            continue;
+        }
+        // gone since its parent listed it
+        let stat = match read_stat(pid) {
+            Some(stat) => stat,
+            None => continue,
+        };
+        table.insert(pid, stat);
+        queue.extend(read_children(pid));
+    }
+
+    return table;
+}
+
+/// Measures the usage of process trees, such as a watcher with its builds or an entry point
+/// with its workers. Cpu usage is measured between two refreshes of the same sampler, so
+/// keep one around for repeated samples
+#[derive(Default)]
+pub struct ProcessSampler {
+    system: System,
+    /// Parent and number of threads of the processes of the trees at the last refresh
+    table: HashMap<u32, (u32, u64)>,
+}
+
+impl ProcessSampler {
+    /// `roots` along with all their descendants, each once
+    fn tree(&self, roots: &[u32]) -> Vec<u32> {
+        let mut children = HashMap::<u32, Vec<u32>>::new();
+        for (pid, (parent, _)) in &self.table {
+            children.entry(*parent).or_default().push(*pid);
+        }
+
+        let mut seen = HashSet::<u32>::new();
+        let mut tree = Vec::<u32>::new();
+        let mut queue: Vec<u32> = roots
+            .iter()
+            .copied()
+            .filter(|pid| self.table.contains_key(pid))
+            .collect();
+
+        while let Some(pid) = queue.pop() {
+            if !seen.insert(pid) {
+                continue;
+            }
+            tree.push(pid);
+            queue.extend(children.get(&pid).into_iter().flatten());
+        }
+
+        return tree;
+    }
+
+    /// Refreshes the trees of `roots` and nothing else. The processes sampled before that
+    /// exited since are forgotten
+    pub fn refresh(&mut self, roots: &[u32]) -> () {
+        self.table = process_table(roots);
+
+        let mut pids: HashSet<Pid> = self.tree(roots).into_iter().map(Pid::from_u32).collect();
+        pids.extend(self.system.processes().keys());
+        let pids: Vec<Pid> = pids.into_iter().collect();
+
+        self.system.refresh_processes_specifics(
+            ProcessesToUpdate::Some(&pids),
+            true,
+            ProcessRefreshKind::nothing()
+                .with_cpu()
+                .with_memory()
+                .without_tasks(),
+        );
+    }
+
+    /// Usage summed over the trees of `roots` at the last refresh, None when none of them
+    /// is running. The uptime is the one of the first root still running
+    pub fn usage(&self, roots: &[u32]) -> Option<ProcessUsage> {
+        let uptime_secs = roots
+            .iter()
+            .find_map(|pid| self.system.process(Pid::from_u32(*pid)))?
+            .run_time();
+
+        let mut usage = ProcessUsage {
+            uptime_secs,
+            ..Default::default()
+        };
+        for pid in self.tree(roots) {
+            let process = match self.system.process(Pid::from_u32(pid)) {
+                Some(process) => process,
+                None => continue,
+            };
+
+            usage.processes += 1;
+            usage.cpu_percent += process.cpu_usage();
+            usage.memory_bytes += process.memory();
+            usage.threads += self
+                .table
+                .get(&pid)
+                .map(|(_, threads)| *threads)
+                .unwrap_or(1);
+            usage.open_files += process.open_files().unwrap_or(0) as u64;
+        }
+
+        return Some(usage);
+    }
+}
+
+/// Usage of the trees of every group of roots, measured together over a short interval
+pub fn sample_usage(groups: &[Vec<u32>]) -> Vec<Option<ProcessUsage>> {
+    let roots: Vec<u32> = groups.iter().flatten().copied().collect();
+    let mut sampler = ProcessSampler::default();
+
+    sampler.refresh(&roots);
+    if !roots.is_empty() {
+        thread::sleep(CPU_SAMPLE_INTERVAL.max(MINIMUM_CPU_UPDATE_INTERVAL));
+        sampler.refresh(&roots);
+    }
+
+    return groups.iter().map(|roots| sampler.usage(roots)).collect();
+}
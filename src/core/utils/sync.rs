@@ -0,0 +1,614 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use crate::utils::structs::{CompareBy, FromTo, SyncMode};
+
+use super::cache::hash_bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncAction {
+    Create,
+    Overwrite,
+    Unchanged,
+    Delete,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncItem {
+    pub action: SyncAction,
+    /// Source path, `None` for deletions
+    pub src: Option<PathBuf>,
+    pub dst: PathBuf,
+}
+
+/// Every file operation a `mouve` entry needs, computed before touching the destination
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    pub root: PathBuf,
+    pub items: Vec<SyncItem>,
+}
+
+impl SyncPlan {
+    pub fn count(&self, action: SyncAction) -> usize {
+        return self.items.iter().filter(|i| i.action == action).count();
+    }
+}
+
+fn has_glob(pattern: &str) -> bool {
+    return pattern.contains(['*', '?', '[']);
+}
+
+/// Shell style matching where `*` and `?` stay within a path segment and `**` spans
+/// any number of segments
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.trim_end_matches('/').chars().collect();
+    let path: Vec<char> = path.trim_end_matches('/').chars().collect();
+    return glob_match_from(&pattern, &path);
+}
+
+fn glob_match_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => return path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // `**/` also matches zero directories
+            let rest = match pattern.get(2) {
+                Some('/') => &pattern[3..],
+                _ => &pattern[2..],
+            };
+            for idx in 0..=path.len() {
+                if (idx == 0 || path[idx - 1] == '/') && glob_match_from(rest, &path[idx..]) {
+                    return true;
+                }
+            }
+            return glob_match_from(&pattern[2..], path);
+        }
+        Some('*') => {
+            for idx in 0..=path.len() {
+                if glob_match_from(&pattern[1..], &path[idx..]) {
+                    return true;
+                }
+                if idx < path.len() && path[idx] == '/' {
+                    break;
+                }
+            }
+            return false;
+        }
+        Some('?') => {
+            return match path.first() {
+                Some(c) if *c != '/' => glob_match_from(&pattern[1..], &path[1..]),
+                _ => false,
+            };
+        }
+        Some('[') => {
+            let close = match pattern.iter().position(|c| *c == ']') {
+                Some(pos) => pos,
+                None => {
+                    return path.first() == Some(&'[')
+                        && glob_match_from(&pattern[1..], &path[1..]);
+                }
+            };
+            let class = &pattern[1..close];
+            let (negate, class) = match class.first() {
+                Some('!') | Some('^') => (true, &class[1..]),
+                _ => (false, class),
+            };
+            let c = match path.first() {
+                Some(c) if *c != '/' => *c,
+                _ => return false,
+            };
+            let mut found = false;
+            let mut idx = 0;
+            while idx < class.len() {
+                if idx + 2 < class.len() && class[idx + 1] == '-' {
+                    if class[idx] <= c && c <= class[idx + 2] {
+                        found = true;
+                    }
+                    idx += 3;
+                    continue;
+                }
+                if class[idx] == c {
+                    found = true;
+                }
+                idx += 1;
+            }
+            return found != negate && glob_match_from(&pattern[close + 1..], &path[1..]);
+        }
+        Some(c) => {
+            return path.first() == Some(c) && glob_match_from(&pattern[1..], &path[1..]);
+        }
+    }
+}
+
+fn is_excluded(excludes: &[String], rel_paths: &[&str]) -> bool {
+    for pattern in excludes {
+        for rel in rel_paths {
+            if rel.is_empty() {
+                continue;
+            }
+            if glob_match(pattern, rel) {
+                return true;
+            }
+            // a bare name excludes it wherever it sits, like rsync
+            if !pattern.contains('/')
+                && let Some(name) = Path::new(rel).file_name()
+                && glob_match(pattern, &name.to_string_lossy())
+            {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+fn relative(path: &Path, base: &Path) -> String {
+    return path
+        .strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string();
+}
+
+/// Walks `dir`, returning every file and symlink below it. Directories are followed
+/// but symlinks to directories are kept as links.
+fn walk(dir: &Path, out: &mut Vec<PathBuf>) -> Result<(), String> {
+    let content = match fs::read_dir(dir) {
+        Ok(content) => content,
+        Err(err) => return Err(format!("{} : {}", dir.display(), err)),
+    };
+
+    for entry in content.flatten() {
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(t) => t,
+            Err(err) => return Err(format!("{} : {}", path.display(), err)),
+        };
+
+        if file_type.is_dir() {
+            walk(&path, out)?;
+            continue;
+        }
+        out.push(path);
+    }
+
+    return Ok(());
+}
+
+/// Resolves `from` (relative to the repository) into the files to transfer, keyed by
+/// their path relative to the destination. A trailing `/` transfers the contents of a
+/// directory, glob matches keep their path relative to the pattern's literal prefix.
+pub fn resolve_sources(
+    repo_dir: &str,
+    entry: &FromTo,
+) -> Result<BTreeMap<String, PathBuf>, String> {
+    let repo = Path::new(repo_dir);
+    let excludes = entry.exclude.clone().unwrap_or_default();
+
+    // (matched path, base the destination path is relative to)
+    let mut roots = Vec::<(PathBuf, PathBuf)>::new();
+
+    if has_glob(&entry.from) {
+        let literal: Vec<&str> = entry
+            .from
+            .split('/')
+            .take_while(|part| !has_glob(part))
+            .collect();
+        let base = repo.join(literal.join("/"));
+
+        let mut candidates = Vec::<PathBuf>::new();
+        let mut dirs = vec![base.clone()];
+        while let Some(dir) = dirs.pop() {
+            let content = match fs::read_dir(&dir) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            for item in content.flatten() {
+                let path = item.path();
+                if item.file_name() == ".git" {
+                    continue;
+                }
+                if item.file_type().is_ok_and(|t| t.is_dir()) {
+                    dirs.push(path.clone());
+                }
+                candidates.push(path);
+            }
+        }
+        candidates.sort();
+
+        // a directory match already carries everything below it
+        let mut matched = Vec::<PathBuf>::new();
+        for path in candidates {
+            if matched.iter().any(|m| path.starts_with(m)) {
+                continue;
+            }
+            if glob_match(&entry.from, &relative(&path, repo)) {
+                matched.push(path);
+            }
+        }
+
+        if matched.is_empty() {
+            return Err(format!("`{}` did not match any file", &entry.from));
+        }
+
+        for path in matched {
+            roots.push((path, base.clone()));
+        }
+    } else {
+        let path = repo.join(&entry.from);
+        if fs::symlink_metadata(&path).is_err() {
+            return Err(format!("`{}` does not exist", &entry.from));
+        }
+
+        let base = match entry.from.ends_with('/') {
+            true => path.clone(),
+            false => path.parent().unwrap_or(repo).to_path_buf(),
+        };
+        roots.push((path, base));
+    }
+
+    let mut sources = BTreeMap::<String, PathBuf>::new();
+
+    for (root, base) in roots {
+        let is_dir = fs::symlink_metadata(&root).is_ok_and(|meta| meta.is_dir());
+
+        let mut files = Vec::<PathBuf>::new();
+        match is_dir {
+            true => walk(&root, &mut files)?,
+            false => files.push(root.clone()),
+        }
+
+        for file in files {
+            let dest_rel = relative(&file, &base);
+            let repo_rel = relative(&file, repo);
+
+            // check the file and every directory above it, up to the base
+            let mut excluded = false;
+            let mut current = Some(file.as_path());
+            while let Some(path) = current {
+                if !path.starts_with(&base) || path == base {
+                    break;
+                }
+                if is_excluded(&excludes, &[&relative(path, &base), &relative(path, repo)]) {
+                    excluded = true;
+                    break;
+                }
+                current = path.parent();
+            }
+            if excluded || is_excluded(&excludes, &[&repo_rel]) {
+                continue;
+            }
+
+            sources.insert(dest_rel, file);
+        }
+    }
+
+    return Ok(sources);
+}
+
+fn same_file(src: &Path, dst: &Path, compare: &CompareBy) -> bool {
+    let (src_meta, dst_meta) = match (fs::symlink_metadata(src), fs::symlink_metadata(dst)) {
+        (Ok(s), Ok(d)) => (s, d),
+        _ => return false,
+    };
+
+    if src_meta.file_type().is_symlink() || dst_meta.file_type().is_symlink() {
+        return match (fs::read_link(src), fs::read_link(dst)) {
+            (Ok(s), Ok(d)) => s == d,
+            _ => false,
+        };
+    }
+
+    if src_meta.len() != dst_meta.len() {
+        return false;
+    }
+
+    match compare {
+        CompareBy::Mtime => match (src_meta.modified(), dst_meta.modified()) {
+            (Ok(s), Ok(d)) => s == d,
+            _ => false,
+        },
+        CompareBy::Hash => match (fs::read(src), fs::read(dst)) {
+            (Ok(s), Ok(d)) => hash_bytes(&s) == hash_bytes(&d),
+            _ => false,
+        },
+    }
+}
+
+/// Computes what deploying `entry` would do to its destination without changing anything
+pub fn plan_sync(repo_dir: &str, entry: &FromTo) -> Result<SyncPlan, String> {
+    let sources = resolve_sources(repo_dir, entry)?;
+    let mode = entry.mode.clone().unwrap_or_default();
+    let compare = entry.compare.clone().unwrap_or_default();
+    let to = Path::new(&entry.to);
+
+    let mut plan = SyncPlan {
+        root: to.to_path_buf(),
+        items: Vec::new(),
+    };
+
+    for (rel, src) in &sources {
+        let dst = to.join(rel);
+
+        let action = match fs::symlink_metadata(&dst) {
+            Err(_) => SyncAction::Create,
+            Ok(_) if mode != SyncMode::Copy && same_file(src, &dst, &compare) => {
+                SyncAction::Unchanged
+            }
+            Ok(_) => SyncAction::Overwrite,
+        };
+
+        plan.items.push(SyncItem {
+            action,
+            src: Some(src.clone()),
+            dst,
+        });
+    }
+
+    if mode == SyncMode::Mirror && to.is_dir() {
+        let excludes = entry.exclude.clone().unwrap_or_default();
+        let wanted: HashSet<&String> = sources.keys().collect();
+
+        let mut existing = Vec::<PathBuf>::new();
+        walk(to, &mut existing)?;
+        existing.sort();
+
+        for path in existing {
+            let rel = relative(&path, to);
+            // excluded files, or files under an excluded directory, are left alone on the
+            // destination, like rsync
+            let mut excluded = false;
+            let mut current = Some(path.as_path());
+            while let Some(path) = current {
+                if path == to || !path.starts_with(to) {
+                    break;
+                }
+                if is_excluded(&excludes, &[&relative(path, to)]) {
+                    excluded = true;
+                    break;
+                }
+                current = path.parent();
+            }
+            if wanted.contains(&rel) || excluded {
+                continue;
+            }
+            plan.items.push(SyncItem {
+                action: SyncAction::Delete,
+                src: None,
+                dst: path,
+            });
+        }
+    }
+
+    return Ok(plan);
+}
+
+fn copy_item(src: &Path, dst: &Path) -> Result<(), String> {
+    if let Some(parent) = dst.parent()
+        && let Err(err) = fs::create_dir_all(parent)
+    {
+        return Err(format!("{} : {}", parent.display(), err));
+    }
+
+    let meta = match fs::symlink_metadata(src) {
+        Ok(meta) => meta,
+        Err(err) => return Err(format!("{} : {}", src.display(), err)),
+    };
+
+    if fs::symlink_metadata(dst).is_ok_and(|m| m.file_type().is_symlink() || m.is_dir()) {
+        let _ = fs::remove_dir_all(dst).or_else(|_| fs::remove_file(dst));
+    }
+
+    if meta.file_type().is_symlink() {
+        let target = match fs::read_link(src) {
+            Ok(target) => target,
+            Err(err) => return Err(format!("{} : {}", src.display(), err)),
+        };
+        let _ = fs::remove_file(dst);
+        return match symlink(&target, dst) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("{} : {}", dst.display(), err)),
+        };
+    }
+
+    if let Err(err) = fs::copy(src, dst) {
+        return Err(format!("{} -> {} : {}", src.display(), dst.display(), err));
+    }
+
+    // keep the source mtime so the next sync can tell the file is unchanged
+    if let Ok(modified) = meta.modified()
+        && let Ok(file) = File::options().write(true).open(dst)
+    {
+        let _ = file.set_modified(modified);
+    }
+
+    return Ok(());
+}
+
+/// Applies a plan, stopping at the first failing operation
+pub fn apply_sync(plan: &SyncPlan) -> Result<(), String> {
+    for item in &plan.items {
+        match (&item.action, &item.src) {
+            (SyncAction::Create, Some(src)) | (SyncAction::Overwrite, Some(src)) => {
+                copy_item(src, &item.dst)?
+            }
+            (SyncAction::Delete, _) => {
+                if let Err(err) = fs::remove_file(&item.dst) {
+                    return Err(format!("{} : {}", item.dst.display(), err));
+                }
+                // drop the directories the deletion left empty
+                let mut parent = item.dst.parent();
+                while let Some(dir) = parent {
+                    if dir == plan.root || !dir.starts_with(&plan.root) {
+                        break;
+                    }
+                    if fs::remove_dir(dir).is_err() {
+                        break;
+                    }
+                    parent = dir.parent();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory under the temp dir holding `files`, each given as (path, content)
+    fn temp_tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("fast_flow-sync-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        return dir;
+    }
+
+    fn entry(from: &str, to: &Path, mode: SyncMode, exclude: &[&str]) -> FromTo {
+        return FromTo {
+            from: from.to_string(),
+            to: to.to_string_lossy().to_string(),
+            exclude: Some(exclude.iter().map(|e| e.to_string()).collect()),
+            mode: Some(mode),
+            ..Default::default()
+        };
+    }
+
+    fn actions(plan: &SyncPlan) -> Vec<(SyncAction, String)> {
+        return plan
+            .items
+            .iter()
+            .map(|item| (item.action.clone(), relative(&item.dst, &plan.root)))
+            .collect();
+    }
+
+    #[test]
+    fn glob_match_keeps_single_wildcards_within_a_segment() {
+        assert!(glob_match("dist/*.js", "dist/app.js"));
+        assert!(!glob_match("dist/*.js", "dist/vendor/app.js"));
+        assert!(glob_match("dist/?pp.js", "dist/app.js"));
+        assert!(!glob_match("dist/?", "dist/"));
+        assert!(glob_match("img/[a-c]*.png", "img/banner.png"));
+        assert!(!glob_match("img/[!a-c]*.png", "img/banner.png"));
+    }
+
+    #[test]
+    fn glob_match_double_star_spans_directories() {
+        assert!(glob_match("dist/**/*.js", "dist/app.js"));
+        assert!(glob_match("dist/**/*.js", "dist/vendor/lib/app.js"));
+        assert!(glob_match("**/node_modules", "web/node_modules"));
+        assert!(!glob_match("dist/**/*.js", "src/app.js"));
+    }
+
+    #[test]
+    fn resolve_sources_leaves_out_the_excludes() {
+        let repo = temp_tree(
+            "excludes",
+            &[
+                ("dist/app.js", "app"),
+                ("dist/app.js.map", "map"),
+                ("dist/assets/logo.png", "png"),
+                ("dist/node_modules/dep/index.js", "dep"),
+            ],
+        );
+        let entry = entry(
+            "dist/",
+            Path::new("/srv/app"),
+            SyncMode::Copy,
+            &["*.map", "node_modules"],
+        );
+
+        let sources = resolve_sources(&repo.to_string_lossy(), &entry).unwrap();
+        let _ = fs::remove_dir_all(&repo);
+
+        assert_eq!(
+            sources.keys().collect::<Vec<_>>(),
+            vec!["app.js", "assets/logo.png"]
+        );
+    }
+
+    #[test]
+    fn resolve_sources_glob_keeps_the_paths_below_its_literal_part() {
+        let repo = temp_tree(
+            "glob",
+            &[
+                ("dist/app.js", "app"),
+                ("dist/vendor/lib.js", "lib"),
+                ("dist/app.css", "css"),
+            ],
+        );
+        let entry = entry("dist/**/*.js", Path::new("/srv/app"), SyncMode::Copy, &[]);
+
+        let sources = resolve_sources(&repo.to_string_lossy(), &entry).unwrap();
+        let _ = fs::remove_dir_all(&repo);
+
+        assert_eq!(
+            sources.keys().collect::<Vec<_>>(),
+            vec!["app.js", "vendor/lib.js"]
+        );
+    }
+
+    #[test]
+    fn sync_skips_files_a_fresh_checkout_left_unchanged() {
+        let repo = temp_tree(
+            "fresh-repo",
+            &[("dist/app.js", "app"), ("dist/main.css", "new")],
+        );
+        let dst = temp_tree("fresh-dst", &[("app.js", "app"), ("main.css", "old")]);
+        let entry = entry("dist/", &dst, SyncMode::Sync, &[]);
+
+        let plan = plan_sync(&repo.to_string_lossy(), &entry).unwrap();
+        let _ = fs::remove_dir_all(&repo);
+        let _ = fs::remove_dir_all(&dst);
+
+        assert_eq!(
+            actions(&plan),
+            vec![
+                (SyncAction::Unchanged, "app.js".to_string()),
+                (SyncAction::Overwrite, "main.css".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn mirror_deletes_what_left_the_source_but_keeps_the_excludes() {
+        let repo = temp_tree("mirror-repo", &[("dist/app.js", "app")]);
+        let dst = temp_tree(
+            "mirror-dst",
+            &[
+                ("app.js", "app"),
+                ("old/stale.js", "stale"),
+                ("uploads/avatar.png", "png"),
+            ],
+        );
+        let entry = entry("dist/", &dst, SyncMode::Mirror, &["uploads"]);
+
+        let plan = plan_sync(&repo.to_string_lossy(), &entry).unwrap();
+        let applied = apply_sync(&plan);
+        let stale_left = dst.join("old").exists();
+        let upload_kept = dst.join("uploads/avatar.png").exists();
+        let _ = fs::remove_dir_all(&repo);
+        let _ = fs::remove_dir_all(&dst);
+
+        assert_eq!(
+            actions(&plan),
+            vec![
+                (SyncAction::Unchanged, "app.js".to_string()),
+                (SyncAction::Delete, "old/stale.js".to_string()),
+            ]
+        );
+        assert!(applied.is_ok());
+        assert!(!stale_left);
+        assert!(upload_kept);
+    }
+}
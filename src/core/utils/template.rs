@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+/// Replaces every `{{ NAME }}` placeholder of `content` with its value from `vars`.
+/// Every missing variable is reported at once instead of rendering an empty string.
+pub fn render_template(content: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::with_capacity(content.len());
+    let mut missing = Vec::<String>::new();
+
+    for (line_idx, line) in content.split_inclusive('\n').enumerate() {
+        let mut rest = line;
+
+        while let Some(start) = rest.find("{{") {
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => {
+                    return Err(format!(
+                        "line {} : unclosed placeholder `{}`",
+                        line_idx + 1,
+                        rest[start..].trim_end()
+                    ));
+                }
+            };
+
+            rendered.push_str(&rest[..start]);
+
+            let name = rest[start + 2..end].trim();
+            match vars.get(name) {
+                Some(value) => rendered.push_str(value),
+                None => missing.push(format!("`{}` (line {})", name, line_idx + 1)),
+            }
+
+            rest = &rest[end + 2..];
+        }
+
+        rendered.push_str(rest);
+    }
+
+    if !missing.is_empty() {
+        return Err(format!("missing variables {}", missing.join(", ")));
+    }
+
+    return Ok(rendered);
+}
@@ -1,39 +1,142 @@
+#![allow(
+    clippy::needless_return,
+    clippy::unused_unit,
+    reason = "house style: explicit returns and unit return types"
+)]
+
 mod core;
 mod utils;
 
 use clap::Parser;
 use utils::{
-    structs::{Cli, Commands},
-    subcommands::{init_config, run_flow, show_logs, show_status, stop_all_track, watch_repo},
+    audit::{api_token, audited_action, begin_audit, end_audit, show_audit},
+    init::init_wizard,
+    logs::show_logs,
+    paths::{Paths, resolve_paths},
+    structs::{CacheCommands, Cli, Commands, ConfigCommands},
+    subcommands::{
+        cache_list, cache_prune, config_edit, config_list, config_remove, config_rename,
+        config_show, deploy, init_config, rollback, run_flow, run_pipeline, show_history,
+        show_status, stop_all_track, validate_configs, watch_repo,
+    },
+    top::top,
 };
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
-    let app_name = format!("fast_flow");
-    let config_dir_path = format!("/etc/{}/config", &app_name);
-    let work_dir = format!("/etc/{}/tmp", &app_name);
-    let process_dir = format!("/etc/{}/process", &app_name);
-    let logs_dir = format!("/etc/{}/logs", &app_name);
+    let Paths {
+        config_dir: config_dir_path,
+        work_dir,
+        cache_dir,
+        process_dir,
+        logs_dir,
+        state_dir,
+    } = resolve_paths(cli.root.clone());
+
+    let audited = audited_action(&cli.command);
+    if let Some((action, config)) = audited {
+        begin_audit(&logs_dir, action, config, api_token(&process_dir));
+    }
 
     match cli.command {
-        Commands::Config(args) => init_config(args.name, &config_dir_path),
+        Commands::Init(args) => init_wizard(&config_dir_path, args),
+        Commands::Config(args) => match args.command {
+            Some(ConfigCommands::Validate(args)) => validate_configs(&config_dir_path, args.name),
+            Some(ConfigCommands::List(args)) => {
+                config_list(&process_dir, &config_dir_path, args.output)
+            }
+            Some(ConfigCommands::Show(args)) => config_show(&config_dir_path, args.name),
+            Some(ConfigCommands::Edit(args)) => config_edit(&config_dir_path, args.name),
+            Some(ConfigCommands::Rm(args)) => config_remove(
+                &work_dir,
+                &cache_dir,
+                &state_dir,
+                &process_dir,
+                &logs_dir,
+                &config_dir_path,
+                args,
+            ),
+            Some(ConfigCommands::Rename(args)) => config_rename(
+                cli.root,
+                &work_dir,
+                &cache_dir,
+                &state_dir,
+                &process_dir,
+                &logs_dir,
+                &config_dir_path,
+                args,
+            ),
+            None => init_config(args.name.unwrap_or_default(), &config_dir_path),
+        },
         Commands::Watch(args) => watch_repo(
+            cli.root,
             &work_dir,
+            &cache_dir,
+            &state_dir,
             &process_dir,
             &logs_dir,
             &config_dir_path,
             args.name,
         ),
         Commands::Stop(args) => stop_all_track(&process_dir, args.name, false),
-        Commands::Status => show_status(&process_dir, &logs_dir, &config_dir_path),
-        Commands::Log(args) => show_logs(&logs_dir, args.name),
+        Commands::Status(args) if args.watch => top(
+            cli.root,
+            &state_dir,
+            &process_dir,
+            &logs_dir,
+            &config_dir_path,
+        ),
+        Commands::Status(args) => {
+            show_status(&state_dir, &process_dir, &logs_dir, &config_dir_path, args)
+        }
+        Commands::Top => top(
+            cli.root,
+            &state_dir,
+            &process_dir,
+            &logs_dir,
+            &config_dir_path,
+        ),
+        Commands::Log(args) => show_logs(&logs_dir, args),
         Commands::Start(args) => run_flow(
             &work_dir,
+            &state_dir,
             &process_dir,
             &logs_dir,
             &config_dir_path,
             args.name,
         ),
+        Commands::Cache(args) => match args.command {
+            CacheCommands::Ls(args) => cache_list(&cache_dir, args),
+            CacheCommands::Prune(args) => cache_prune(&cache_dir, &config_dir_path, args),
+        },
+        Commands::Deploy(args) => deploy(
+            &work_dir,
+            &cache_dir,
+            &state_dir,
+            &process_dir,
+            &config_dir_path,
+            args,
+        ),
+        Commands::Rollback(args) => rollback(
+            &work_dir,
+            &cache_dir,
+            &state_dir,
+            &process_dir,
+            &config_dir_path,
+            args,
+        ),
+        Commands::History(args) => show_history(&state_dir, &config_dir_path, args),
+        Commands::Run(args) => run_pipeline(
+            &work_dir,
+            &cache_dir,
+            &state_dir,
+            &process_dir,
+            &config_dir_path,
+            args,
+        ),
+        Commands::Audit(args) => show_audit(&logs_dir, args),
     }
+
+    end_audit();
 }
@@ -0,0 +1,338 @@
+use std::{
+    collections::VecDeque,
+    ffi::CStr,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    mem,
+    os::unix::fs::OpenOptionsExt,
+    ptr,
+    sync::Mutex,
+};
+
+use crate::core::utils::filesystem::read_from_file_ut;
+
+use super::{
+    logs::parse_since,
+    output::format_records,
+    server::HTTP_PID,
+    structs::{AppState, AuditArgs, AuditEntry, Commands, ConfigCommands, OutputFormat},
+    table::create_table,
+};
+
+const AUDIT_LOG: &str = "fast_flow.audit.log";
+
+/// Set on the flow commands the HTTP API runs, names the token they run for. It is only
+/// believed when the HTTP daemon is the parent process, see `api_token`
+pub const AUDIT_TOKEN_ENV: &str = "FLOW_AUDIT_TOKEN";
+
+/// The CLI action being run, recorded once it is over
+struct AuditContext {
+    logs_dir: String,
+    action: &'static str,
+    config: Option<String>,
+    token: Option<String>,
+    outcome: String,
+    reason: Option<String>,
+}
+
+static CURRENT: Mutex<Option<AuditContext>> = Mutex::new(None);
+
+/// Name and configuration of the action `command` is, None for the ones that change nothing
+pub fn audited_action(command: &Commands) -> Option<(&'static str, Option<String>)> {
+    match command {
+        Commands::Init(args) => return Some(("config create", args.name.clone())),
+        Commands::Config(args) => match &args.command {
+            None => return Some(("config create", args.name.clone())),
+            Some(ConfigCommands::Edit(args)) => {
+                return Some(("config edit", Some(args.name.clone())));
+            }
+            Some(ConfigCommands::Rm(args)) => {
+                return Some(("config delete", Some(args.name.clone())));
+            }
+            Some(ConfigCommands::Rename(args)) => {
+                return Some(("config rename", Some(args.name.clone())));
+            }
+            Some(_) => return None,
+        },
+        Commands::Watch(args) => return Some(("watch", args.name.clone())),
+        Commands::Stop(args) => return Some(("stop", args.name.clone())),
+        Commands::Start(args) => return Some(("start", args.name.clone())),
+        Commands::Deploy(args) => return Some(("deploy", Some(args.name.clone()))),
+        Commands::Rollback(args) => return Some(("rollback", Some(args.name.clone()))),
+        Commands::Run(args) if !args.dry_run => return Some(("run", Some(args.name.clone()))),
+        _ => return None,
+    }
+}
+
+/// Token the HTTP API started this process for. Anyone can set AUDIT_TOKEN_ENV, so it
+/// only counts when the parent is the running HTTP daemon
+pub fn api_token(process_dir: &str) -> Option<String> {
+    let token = std::env::var(AUDIT_TOKEN_ENV).ok()?;
+    let parent = unsafe { libc::getppid() };
+
+    let daemon = read_from_file_ut(&format!("{}/{}", process_dir, HTTP_PID))
+        .ok()
+        .and_then(|pid| pid.trim().parse::<i32>().ok());
+    // a stale pid file may name a pid that was reused since
+    let same_exe =
+        fs::read_link(format!("/proc/{}/exe", parent)).ok() == std::env::current_exe().ok();
+
+    match daemon == Some(parent) && same_exe {
+        true => return Some(token),
+        false => return None,
+    }
+}
+
+/// Starts recording `action`, a success unless told otherwise before `end_audit`. `token`
+/// names the API token it runs for
+pub fn begin_audit(
+    logs_dir: &str,
+    action: &'static str,
+    config: Option<String>,
+    token: Option<String>,
+) -> () {
+    *CURRENT.lock().unwrap() = Some(AuditContext {
+        logs_dir: logs_dir.to_string(),
+        action,
+        config,
+        token,
+        outcome: "success".to_string(),
+        reason: None,
+    });
+}
+
+/// Records how the action being run ended, such as `failed` or `cancelled`, and why
+pub fn set_outcome(outcome: &str, reason: Option<String>) -> () {
+    if let Some(current) = CURRENT.lock().unwrap().as_mut() {
+        current.outcome = outcome.to_string();
+        current.reason = reason;
+    }
+}
+
+/// Prints why the action failed and records it as its outcome
+pub fn fail(message: String) -> () {
+    println!("{}", message);
+    set_outcome("failed", Some(message));
+}
+
+/// Outcome of a deploy, told by the run it left in the state, if any
+pub fn set_run_outcome(before: &AppState, after: &AppState) -> () {
+    let run_id = |state: &AppState| {
+        state
+            .last_deploy
+            .as_ref()
+            .map(|deploy| deploy.run_id.clone())
+    };
+
+    match &after.last_deploy {
+        Some(deploy) if run_id(after) != run_id(before) && deploy.result != "success" => {
+            set_outcome(
+                &deploy.result,
+                Some(format!(
+                    "run {} failed in {} : {}",
+                    &deploy.run_id,
+                    deploy.stage.clone().unwrap_or_default(),
+                    deploy.error.clone().unwrap_or_default()
+                )),
+            );
+        }
+        Some(deploy) if run_id(after) != run_id(before) => {
+            set_outcome("success", Some(format!("run {}", &deploy.run_id)));
+        }
+        _ if after.metrics.poll_errors > before.metrics.poll_errors => {
+            set_outcome(
+                "failed",
+                Some("the run could not start, see flow log".to_string()),
+            );
+        }
+        _ => set_outcome("unchanged", None),
+    }
+}
+
+/// Records the action being run, once. The watchers end the process of `flow watch`, so it
+/// records before starting them
+pub fn end_audit() -> () {
+    let current = match CURRENT.lock().unwrap().take() {
+        Some(current) => current,
+        None => return,
+    };
+
+    append(
+        &current.logs_dir,
+        AuditEntry {
+            time: chrono::Local::now().to_rfc3339(),
+            source: match current.token {
+                Some(_) => "api".to_string(),
+                None => "cli".to_string(),
+            },
+            user: unix_user(),
+            token: current.token,
+            action: current.action.to_string(),
+            config: current.config,
+            args: std::env::args().skip(1).collect(),
+            outcome: current.outcome,
+            reason: current.reason,
+        },
+    );
+}
+
+/// Records an action asked through the HTTP API with the token named `token`
+pub fn audit_api(
+    logs_dir: &str,
+    token: &str,
+    action: &str,
+    config: &str,
+    args: Vec<String>,
+    outcome: &str,
+    reason: Option<String>,
+) -> () {
+    append(
+        logs_dir,
+        AuditEntry {
+            time: chrono::Local::now().to_rfc3339(),
+            source: "api".to_string(),
+            user: unix_user(),
+            token: Some(token.to_string()),
+            action: action.to_string(),
+            config: Some(config.to_string()),
+            args,
+            outcome: outcome.to_string(),
+            reason,
+        },
+    );
+}
+
+/// Name of the user `uid`, None when the user database doesn't know it
+fn user_name(uid: libc::uid_t) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+
+    loop {
+        let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let err =
+            unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+
+        match err {
+            0 if !result.is_null() => {
+                let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+                return Some(name.to_string_lossy().to_string());
+            }
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            _ => return None,
+        }
+    }
+}
+
+/// Real unix user flow runs as, followed by the one sudo says ran it. SUDO_USER can be set
+/// by hand, so it never replaces the real user
+fn unix_user() -> String {
+    let uid = unsafe { libc::getuid() };
+    let user = user_name(uid).unwrap_or(uid.to_string());
+
+    match std::env::var("SUDO_USER") {
+        Ok(sudo_user) if sudo_user != user => {
+            return format!("{} via sudo from {}", user, sudo_user);
+        }
+        _ => return user,
+    }
+}
+
+/// Adds `entry` as a single line, the log is only ever appended to
+fn append(logs_dir: &str, entry: AuditEntry) -> () {
+    let path = format!("{}/{}", logs_dir, AUDIT_LOG);
+    let line = format!("{}\n", serde_json::to_string(&entry).unwrap());
+
+    // flow init may be the first command run on a new root
+    let _ = fs::create_dir_all(logs_dir);
+    let res = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o640)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+
+    if let Err(err) = res {
+        eprintln!("could not write the audit log {} : {}", &path, err);
+    }
+}
+
+pub fn show_audit(logs_dir: &str, args: AuditArgs) -> () {
+    let path = format!("{}/{}", logs_dir, AUDIT_LOG);
+
+    let since = match args.since.as_deref().map(parse_since).transpose() {
+        Ok(since) => since,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(_) if args.output == OutputFormat::Table => {
+            println!("No action was recorded yet");
+            return;
+        }
+        Err(_) => {
+            println!("{}", format_records::<AuditEntry>(&[], args.output));
+            return;
+        }
+    };
+
+    let mut entries = VecDeque::<AuditEntry>::new();
+    for line in BufReader::new(file).lines() {
+        let entry = match line
+            .ok()
+            .and_then(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+        {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        if args.name.is_some() && entry.config != args.name {
+            continue;
+        }
+        if let Some(user) = &args.user {
+            let by_user = entry.user == *user
+                || entry.user.starts_with(&format!("{} ", user))
+                || entry.user.ends_with(&format!(" from {}", user));
+            if !by_user && entry.token.as_ref() != Some(user) {
+                continue;
+            }
+        }
+        if args
+            .action
+            .as_ref()
+            .is_some_and(|action| entry.action != *action)
+        {
+            continue;
+        }
+        if let Some(since) = since {
+            let time = chrono::DateTime::parse_from_rfc3339(&entry.time)
+                .map(|time| time.with_timezone(&chrono::Local).naive_local());
+            if time.is_ok_and(|time| time < since) {
+                continue;
+            }
+        }
+
+        entries.push_back(entry);
+        if args.tail.is_some_and(|tail| entries.len() > tail) {
+            entries.pop_front();
+        }
+    }
+
+    let entries: Vec<AuditEntry> = entries.into();
+
+    if args.output != OutputFormat::Table {
+        println!("{}", format_records(&entries, args.output));
+        return;
+    }
+
+    if entries.is_empty() {
+        println!("No recorded action matches");
+        return;
+    }
+
+    let table = create_table(&entries, "Fast⚡Flow Audit Log");
+    println!("{table}");
+}
@@ -1,25 +1,111 @@
 pub fn config_example() -> String {
-    return format!(
-        "{{
+    return "{
     // BUILD CONFIGURATION
     // Array of shell commands to compile the application
     // Executed in sequence from the repository root
+    // A step can also be an object with:
+    // - \"run\": Command to execute
+    // - \"timeout\": Seconds before the step and all its children are killed
+    // - \"cwd\": Working directory (relative to repo root unless absolute)
+    // - \"env\": Extra environment variables
+    // - \"continue_on_error\": Keep building if this step fails
+    // - \"shell\": Shell used to run the command (default sh)
+    // - \"host\": Run the step over ssh on a host of \"hosts\" (remote restarts, migrations)
+    // - \"name\" / \"needs\": Name the step and list the steps it waits for,
+    //   steps without pending needs run in parallel (up to \"max_parallel\")
+    //   otherwise the steps run one after the other
     \"build\": [
-        \"cargo build --release\"  // example Rust release build or npm run build
+        \"cargo build --release\",  // example Rust release build or npm run build
+        {
+            \"run\": \"npm install\",
+            \"timeout\": 600,
+            \"cwd\": \"frontend\",
+            \"env\": { \"NODE_ENV\": \"production\" }
+        }
     ],
 
     // DEPLOYMENT MAPPING
     // Array of file operations to deploy build artifacts
     // Each entry specifies:
-    // - \"from\": Source path (relative to repo root)
-    // - \"to\": Absolute destination path on target system
+    // - \"from\": Source path or glob (relative to repo root),
+    //   a trailing / deploys the contents of the directory
+    // - \"to\": Absolute destination path on target system,
+    //   or \"host:path\" to rsync the files to a remote host over ssh
+    // - \"exclude\": Optional globs left out of the deploy
+    // - \"mode\": \"copy\" (default) overwrites everything, \"sync\" copies changed files only,
+    //   \"mirror\" also deletes destination files missing from the source
+    // - \"compare\": How sync detects changes, \"hash\" (size and content, default) or
+    //   \"mtime\" (size and mtime, faster but every file of a fresh checkout looks changed)
+    // - \"owner\" / \"group\": Owner of the deployed files (name or id)
+    // - \"file_mode\" / \"dir_mode\": Octal modes such as \"0644\" and \"0755\"
     \"mouve\": [
-        {{
+        {
             \"from\": \"target/release/myapp\",  // Built binary
             \"to\": \"/var/www/api.myapp/\"  // Production Directory location
-    }}
+        },
+        {
+            \"from\": \"frontend/dist/\",
+            \"to\": \"/var/www/myapp/\",
+            \"exclude\": [\"maps\"],
+            \"mode\": \"mirror\",
+            \"owner\": \"www-data\",
+            \"group\": \"www-data\",
+            \"file_mode\": \"0644\",
+            \"dir_mode\": \"0755\"
+        }
     ],
 
+    // VARIABLES AND TEMPLATES
+    // - \"env\": Environment variables given to every build step and hook
+    // - \"vars\": Extra variables only used by templates
+    // - \"secrets_file\": KEY=value file whose entries are available to templates
+    // - \"templates\": Files of the repository rendered on every deploy, each
+    //   {{ NAME }} is replaced by its value from env, vars, secrets or the deploy
+    //   metadata (FLOW_COMMIT_SHA, FLOW_BRANCH, FLOW_RUN_ID ...), a missing one fails the deploy
+    \"env\": { \"APP_ENV\": \"production\" },
+    \"vars\": { \"PORT\": \"8080\" },
+    \"templates\": [
+        {
+            \"from\": \"deploy/app.env.tmpl\",
+            \"to\": \"/etc/myapp/app.env\",
+            \"file_mode\": \"0640\"
+        }
+    ],
+
+    // REMOTE HOSTS
+    // SSH connection settings used by \"host:path\" destinations and step hosts
+    // A deploy fails if any host fails, each host result is logged
+    \"hosts\": {
+        \"web1\": {
+            \"host\": \"10.0.0.5\",
+            \"user\": \"deploy\",
+            \"port\": 22,
+            \"identity_file\": \"/root/.ssh/id_ed25519\"
+        }
+    },
+
+    // ARTIFACT CACHE
+    // The \"mouve\" sources are archived per commit and build configuration,
+    // redeploying a known commit restores them instead of rebuilding
+    // Inspect or clean it with `flow cache ls` and `flow cache prune`
+    \"cache\": {
+        \"enabled\": true,
+        \"max_size_mb\": 1024,
+        \"max_age_days\": 30
+    },
+
+    // PIPELINE HOOKS
+    // Optional step lists using the same format as \"build\"
+    // Every step receives FLOW_COMMIT_SHA, FLOW_BRANCH, FLOW_RUN_ID, FLOW_BUILD_DIR (the checkout)
+    // and FLOW_RELEASE_PATH (the \"mouve\" destinations, separated by ':')
+    // - \"pre_build\": Before the build (fetching assets)
+    // - \"pre_deploy\": Before moving the artifacts (migrations, stopping workers)
+    // - \"post_deploy\": After the artifacts are in place (cache warmup, CDN purge)
+    // - \"on_failure\": When any stage fails, with FLOW_FAILED_STAGE set
+    \"pre_deploy\": [],
+    \"post_deploy\": [],
+    \"on_failure\": [],
+
     entry_point:[],
 
     // REPOSITORY CONFIGURATION
@@ -27,5 +113,5 @@ pub fn config_example() -> String {
     // Supports both HTTPS and SSH formats
     \"repo\": \"https://github.com/MyUser/myapp.git\"
     "
-    );
+    .to_string();
 }
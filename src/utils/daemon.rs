@@ -1,24 +1,143 @@
-use std::{thread, time::Duration};
+use std::{
+    collections::HashSet,
+    fs,
+    io::Write,
+    net::TcpListener,
+    path::Path,
+    process::exit,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
 
-use daemonize::Daemonize;
-use fern::Dispatch;
-use log::info;
+use daemonize::{Daemonize, Outcome};
+use log::{info, warn};
 
-use super::utils::{execute_commande, read_from_file_ut};
+use crate::core::utils::{
+    command::execute_commande,
+    filesystem::{load_file_parsed, read_from_file_ut},
+    rotate::LogFile,
+};
 
+use super::{
+    logger::{log_format, logger, set_log_config},
+    notify::{Notification, notifiers_for, notify},
+    structs::{ConfigFile, NotifyEvent},
+    utils::{config_name, rotate_log},
+};
+
+/// Set by SIGHUP, an external logrotate sends it once it moved the log away
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_hangup(_signal: libc::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+/// Rotates the logs of the watched config when its `logs` policy asks for it, and reopens
+/// the watcher log after a rotation or a SIGHUP
+fn maintain_logs(
+    log_file: &LogFile,
+    log_file_path: &str,
+    process_log_path: &str,
+    config_file_path: &str,
+) -> () {
+    let logs = load_file_parsed::<ConfigFile>(config_file_path)
+        .ok()
+        .and_then(|config| config.logs)
+        .unwrap_or_default();
+
+    let mut reopen = HANGUP.swap(false, Ordering::SeqCst);
+
+    match rotate_log(log_file_path, &logs, false) {
+        Ok(rotated) => reopen = reopen || rotated,
+        Err(err) => warn!("Failed to rotate {} : {}", log_file_path, err),
+    }
+
+    if reopen {
+        match log_file.reopen() {
+            Ok(_) => {
+                // stray prints of the watcher follow the log too
+                let fd = log_file.raw_fd();
+                unsafe {
+                    libc::dup2(fd, libc::STDOUT_FILENO);
+                    libc::dup2(fd, libc::STDERR_FILENO);
+                }
+                info!("Reopened {}", log_file_path);
+            }
+            Err(err) => warn!("Failed to reopen {} : {}", log_file_path, err),
+        }
+    }
+
+    // the started apps hold their log open, it can only be copied and truncated
+    match rotate_log(process_log_path, &logs, true) {
+        Ok(true) => info!("Rotated {}", process_log_path),
+        Ok(false) => {}
+        Err(err) => warn!("Failed to rotate {} : {}", process_log_path, err),
+    }
+}
+
+/// Tells the notifiers about the entry points of `name` that exited since the last poll.
+/// Stopping them empties the pid file, so only the pids still listed have crashed
+fn check_apps(
+    app_pid_path: &str,
+    config_file_path: &str,
+    name: &str,
+    running: &mut HashSet<String>,
+) -> () {
+    let listed: Vec<String> = read_from_file_ut(app_pid_path)
+        .unwrap_or_default()
+        .lines()
+        .map(|pid| pid.trim().to_string())
+        .filter(|pid| !pid.is_empty())
+        .collect();
+
+    // pids of stopped or replaced entry points are forgotten
+    running.retain(|pid| listed.contains(pid));
+
+    for pid in listed {
+        let alive = Path::new(&format!("/proc/{}", &pid)).exists();
+
+        if alive {
+            running.insert(pid);
+            continue;
+        }
+        if !running.remove(&pid) {
+            continue;
+        }
+
+        warn!("Entry point {} of [{}] exited", &pid, name);
+        let notifiers = notifiers_for(
+            load_file_parsed::<ConfigFile>(config_file_path)
+                .ok()
+                .and_then(|config| config.notify),
+        );
+        notify(
+            &notifiers,
+            Notification::for_app(
+                NotifyEvent::AppCrashed,
+                name,
+                format!("entry point {} of [{}] exited", &pid, name),
+            ),
+        );
+    }
+}
+
+#[allow(
+    clippy::too_many_arguments,
+    reason = "each directory of the layout is passed on its own"
+)]
 pub fn daemonizer(
     name: String,
     work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
     config_file_path: &str,
     pid_file_path: &str,
     log_file_path: &str,
-    cb: fn(&str, &str) -> (),
+    cb: fn(&str, &str, &str, &str) -> (),
 ) -> () {
-    match read_from_file_ut(pid_file_path) {
-        Ok(pid) => {
-            let _ = execute_commande(&format!("kill {}", pid.trim()));
-        }
-        Err(_) => {}
+    if let Ok(pid) = read_from_file_ut(pid_file_path) {
+        let _ = execute_commande(&format!("kill {}", pid.trim()));
     }
 
     // Daemonize to detach from the terminal and run in the background
@@ -26,24 +145,31 @@ pub fn daemonizer(
         .pid_file(pid_file_path) // Prevent multiple instances
         .chown_pid_file(true) // Allow writing to the PID file
         .working_directory(".")
-        .stdout(fern::log_file(&log_file_path).unwrap()) // Redirect stdout to log
-        .stderr(fern::log_file(&log_file_path).unwrap()); // Redirect stderr to log
+        .stdout(fern::log_file(log_file_path).unwrap()) // Redirect stdout to log
+        .stderr(fern::log_file(log_file_path).unwrap()); // Redirect stderr to log
     // .privileged_action(|| println!("Background process started"));
 
     match daemonize.start() {
         Ok(_) => {
-            // init loggoing
-            Dispatch::new()
-                .format(|out, message, record| {
-                    out.finish(format_args!(
-                        "[{}][{}] {}",
-                        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                        record.level(),
-                        message
-                    ))
-                })
-                .level(log::LevelFilter::Debug) // Adjust log level as needed
-                .chain(fern::log_file(log_file_path).expect("Failed to create log file"))
+            let log_file = LogFile::open(log_file_path).expect("Failed to create log file");
+            let process_log_path = Path::new(log_file_path)
+                .with_file_name(format!("{}.process.log", &name))
+                .to_string_lossy()
+                .to_string();
+            let app_pid_path = Path::new(pid_file_path)
+                .with_file_name(format!("{}.process.pid", &name))
+                .to_string_lossy()
+                .to_string();
+            let mut running_apps = HashSet::<String>::new();
+
+            unsafe {
+                libc::signal(libc::SIGHUP, on_hangup as *const () as libc::sighandler_t);
+            }
+
+            // init loggoing, a change of format is picked up when the watcher restarts
+            set_log_config(&name);
+            logger(log_format(config_file_path))
+                .chain(Box::new(log_file.clone()) as Box<dyn Write + Send>)
                 .apply()
                 .expect("Failed to initialize logger");
 
@@ -51,8 +177,15 @@ pub fn daemonizer(
             info!("Started New instance watching [{}] ", &name);
             info!("<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<");
             loop {
+                maintain_logs(
+                    &log_file,
+                    log_file_path,
+                    &process_log_path,
+                    config_file_path,
+                );
+                check_apps(&app_pid_path, config_file_path, &name, &mut running_apps);
                 // Run the flow
-                let _ = cb(work_dir, config_file_path);
+                cb(work_dir, cache_dir, state_dir, config_file_path);
                 // Wait for 5 second
                 thread::sleep(Duration::from_secs(5));
             }
@@ -61,6 +194,14 @@ pub fn daemonizer(
     }
 }
 
+/// Same log format as the daemon, printed to the terminal for foreground runs
+pub fn console_logger(config_file_path: &str) -> () {
+    set_log_config(&config_name(config_file_path));
+    let _ = logger(log_format(config_file_path))
+        .chain(std::io::stdout())
+        .apply();
+}
+
 // change the name of the daemonized process TODO!
 // use libc;
 // use std::ffi::CString;
@@ -87,3 +228,52 @@ pub fn daemonizer(
 // Daemonize::new()
 //     .privileged_action(|| set_process_name("my_custom_daemon"))
 //     .start();
+
+/// Binds `listen` and detaches a daemon to serve it, unless the one of `pid_file_path` is
+/// still running. The listener is given to the daemon, the caller gets None and goes on
+pub fn serve_in_background(
+    what: &str,
+    listen: &str,
+    path: &str,
+    pid_file_path: &str,
+    log_file_path: &str,
+) -> Option<TcpListener> {
+    if let Ok(pid) = read_from_file_ut(pid_file_path) {
+        let pid = pid.trim();
+        if !pid.is_empty() && fs::metadata(format!("/proc/{}", pid)).is_ok() {
+            return None;
+        }
+    }
+
+    // bound before detaching so an address in use is reported here
+    let listener = match TcpListener::bind(listen) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("{} not started, {} : {}", what, listen, err);
+            return None;
+        }
+    };
+
+    let daemonize = Daemonize::new()
+        .pid_file(pid_file_path)
+        .chown_pid_file(true)
+        .working_directory(".")
+        .stdout(fern::log_file(log_file_path).unwrap())
+        .stderr(fern::log_file(log_file_path).unwrap());
+
+    match daemonize.execute() {
+        Outcome::Parent(Ok(_)) => {
+            println!("{} served on http://{}{}", what, listen, path);
+            return None;
+        }
+        Outcome::Parent(Err(err)) => {
+            println!("Failed to start the {}: {}", what, err);
+            return None;
+        }
+        Outcome::Child(Ok(_)) => return Some(listener),
+        Outcome::Child(Err(err)) => {
+            eprintln!("Failed to start the {}: {}", what, err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::core::utils::{
+    cache::{has_artifacts, restore_artifacts},
+    filesystem::{load_file_parsed, read_from_file_ut},
+    git::extract_repo_info,
+    remote::{parse_target, push_to_remote, remote_step, resolve_host},
+    sync::{SyncAction, plan_sync},
+};
+
+use super::{
+    daemon::console_logger,
+    stages::{StageContext, run_stage},
+    structs::{BuildStep, ConfigFile},
+    utils::{
+        artifacts_cache_key, clone_at, config_name, default_max_parallel, deploy_metadata,
+        fetch_remote_version, render_templates, template_vars,
+    },
+};
+
+fn print_stage(
+    stage: &str,
+    steps: &[BuildStep],
+    env: &HashMap<String, String>,
+    config: &ConfigFile,
+) {
+    if steps.is_empty() {
+        return;
+    }
+
+    println!("\n[{}]", stage);
+
+    let hosts = config.hosts.clone().unwrap_or_default();
+
+    for (idx, build_step) in steps.iter().enumerate() {
+        let mut step = build_step.to_step();
+        let label = step.name.clone().unwrap_or(format!("step {}", idx + 1));
+
+        let mut step_env = env.clone();
+        step_env.extend(step.env.clone().unwrap_or_default());
+        step.env = Some(step_env.clone());
+
+        println!("  {} : {}", label, &step.run);
+        if let Some(needs) = &step.needs {
+            println!("    needs   : {}", needs.join(", "));
+        }
+        if let Some(cwd) = &step.cwd {
+            println!("    cwd     : {}", cwd);
+        }
+        if let Some(timeout) = step.timeout {
+            println!("    timeout : {}s", timeout);
+        }
+        if let Some(shell) = &step.shell {
+            println!("    shell   : {}", shell);
+        }
+        if step.continue_on_error.unwrap_or(false) {
+            println!("    continue_on_error");
+        }
+        if let Some(host) = &step.host {
+            println!("    host    : {}", host);
+            println!(
+                "    over ssh: {}",
+                remote_step(&step, &resolve_host(host, &hosts)).run
+            );
+        }
+
+        let mut keys: Vec<&String> = step_env.keys().collect();
+        keys.sort();
+        for key in keys {
+            println!("    env     : {}={}", key, &step_env[key]);
+        }
+    }
+}
+
+fn print_sync_plans(config: &ConfigFile, repo_dir: &str, stage_root: &str) -> () {
+    let hosts = config.hosts.clone().unwrap_or_default();
+
+    for (idx, entry) in config.mouve.iter().enumerate() {
+        println!("\n[mouve] {} -> {}", &entry.from, &entry.to);
+
+        if let Some(target) = parse_target(&entry.to, &hosts) {
+            let stage_dir = format!("{}/stage-{}", stage_root, idx);
+            match push_to_remote(repo_dir, &stage_dir, entry, &target, true) {
+                Ok((_, output)) => {
+                    println!("  rsync to host {} (dry run) :", &target.name);
+                    for line in output.lines() {
+                        println!("    {}", line);
+                    }
+                }
+                Err(err) => println!("  ✖ {}", err),
+            }
+            continue;
+        }
+
+        let plan = match plan_sync(repo_dir, entry) {
+            Ok(plan) => plan,
+            Err(err) => {
+                println!("  ✖ {}", err);
+                continue;
+            }
+        };
+
+        for item in &plan.items {
+            let action = match item.action {
+                SyncAction::Create => "copy",
+                SyncAction::Overwrite => "overwrite",
+                SyncAction::Delete => "delete",
+                SyncAction::Unchanged => continue,
+            };
+            println!("  {:<9} {}", action, item.dst.display());
+        }
+
+        println!(
+            "  {} copied, {} overwritten, {} unchanged, {} deleted",
+            plan.count(SyncAction::Create),
+            plan.count(SyncAction::Overwrite),
+            plan.count(SyncAction::Unchanged),
+            plan.count(SyncAction::Delete)
+        );
+
+        for (label, value) in [
+            ("owner", &entry.owner),
+            ("group", &entry.group),
+            ("file_mode", &entry.file_mode),
+            ("dir_mode", &entry.dir_mode),
+        ] {
+            if let Some(value) = value {
+                println!("  {:<9} {}", label, value);
+            }
+        }
+    }
+}
+
+fn print_processes(config: &ConfigFile, process_dir: &str, name: &str) -> () {
+    println!("\n[process]");
+
+    let entries: Vec<String> = config
+        .entry_point
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .collect();
+
+    if entries.is_empty() {
+        println!("  no entry point configured, nothing would be restarted");
+        return;
+    }
+
+    let pids =
+        read_from_file_ut(&format!("{}/{}.process.pid", process_dir, name)).unwrap_or_default();
+    let running: Vec<&str> = pids
+        .lines()
+        .map(|pid| pid.trim())
+        .filter(|pid| !pid.is_empty() && Path::new(&format!("/proc/{}", pid)).exists())
+        .collect();
+
+    for entry in entries {
+        println!("  restart {} (flow start -n {})", entry, name);
+    }
+    match running.len() {
+        0 => println!("  not running right now"),
+        _ => println!("  running now with pid {}", running.join(", ")),
+    }
+}
+
+/// `dir` with its `.` and `..` resolved without touching the filesystem
+fn normalize(dir: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in dir.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    return normalized;
+}
+
+/// Runs the pre_build and build stages in the throwaway checkout so the sync plans list
+/// the files the build produces, its state is kept under `tmp_dir` as well
+fn run_build(
+    config: &ConfigFile,
+    config_file_path: &str,
+    repo_dir: &str,
+    tmp_dir: &str,
+    env: &HashMap<String, String>,
+) -> Result<(), String> {
+    let stages = [
+        ("pre_build", config.pre_build.clone().unwrap_or_default()),
+        ("build", config.build.clone()),
+    ];
+
+    // a step on a remote host would change that host, the dry run stops short of it,
+    // as it does for a step working in a directory outside the throwaway checkout
+    for (stage, steps) in &stages {
+        for step in steps.iter().map(|step| step.to_step()) {
+            if step.host.is_some() {
+                return Err(format!(
+                    "{} runs steps on remote hosts, the build is not run in a dry run",
+                    stage
+                ));
+            }
+
+            let cwd = match &step.cwd {
+                Some(dir) => normalize(&Path::new(repo_dir).join(dir)),
+                None => PathBuf::from(repo_dir),
+            };
+            if !cwd.starts_with(tmp_dir) {
+                return Err(format!(
+                    "{} runs `{}` in {}, outside the throwaway checkout, the build is not run in a dry run",
+                    stage,
+                    &step.run,
+                    cwd.display()
+                ));
+            }
+        }
+    }
+
+    let ctx = StageContext {
+        repo_dir: repo_dir.to_string(),
+        env: env.clone(),
+        max_parallel: config.max_parallel.unwrap_or(default_max_parallel()),
+        hosts: HashMap::new(),
+        on_failure: Vec::new(),
+        name: config_name(config_file_path),
+        state_dir: format!("{}/state", tmp_dir),
+        started_at: chrono::Local::now(),
+        notify: Vec::new(),
+        host_outcomes: Vec::new(),
+    };
+
+    println!("\nRunning the build in the throwaway checkout");
+    console_logger(config_file_path);
+
+    for (stage, steps) in &stages {
+        if let Err(err) = run_stage(stage, steps, &ctx) {
+            return Err(format!("{} stage failed : {}", stage, err));
+        }
+    }
+
+    return Ok(());
+}
+
+/// Walks through a whole pipeline run of `name` without changing anything outside a
+/// temporary checkout, the build only runs in it when `build` is set
+pub fn dry_run(
+    cache_dir: &str,
+    process_dir: &str,
+    config_dir_path: &str,
+    name: &str,
+    build: bool,
+) -> () {
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, name);
+
+    let config = match load_file_parsed::<ConfigFile>(&config_file_path) {
+        Ok(conf) => conf,
+        Err(err) => {
+            println!("err loading {}", &config_file_path);
+            println!("{err}");
+            return;
+        }
+    };
+
+    let branch = config.branch.clone().unwrap_or("main".to_string());
+
+    let (username, folder_name) = match extract_repo_info(&config.repo) {
+        Some(rep) => rep,
+        None => {
+            println!("error while parsing your github repo to extract the name, check it");
+            return;
+        }
+    };
+
+    println!("Dry run of [{}] : {} ({})", name, &config.repo, &branch);
+
+    let commit = match fetch_remote_version(username, folder_name, &branch) {
+        Ok(v) => v,
+        Err(err) => {
+            println!("✖ could not resolve the branch head : {}", err);
+            return;
+        }
+    };
+
+    let deployed = config.version.clone().unwrap_or_default();
+    println!("Target commit   : {}", &commit);
+    println!(
+        "Deployed commit : {}",
+        match deployed.trim() {
+            "" => "none",
+            v => v,
+        }
+    );
+    if deployed.trim() == commit {
+        println!("Already up to date, the watcher would skip this commit");
+    }
+
+    // everything below happens in a throwaway checkout
+    let tmp_dir = std::env::temp_dir()
+        .join(format!("fast_flow-dry-run-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = fs::remove_dir_all(&tmp_dir);
+    if let Err(err) = fs::create_dir_all(&tmp_dir) {
+        println!("✖ {}", err);
+        return;
+    }
+
+    let repo_dir = format!("{}/{}", &tmp_dir, folder_name);
+
+    if let Err(err) = clone_at(&tmp_dir, username, folder_name, &commit) {
+        println!("✖ clone failed : {}", err);
+        let _ = fs::remove_dir_all(&tmp_dir);
+        return;
+    }
+
+    let metadata = deploy_metadata(&commit, &branch, &repo_dir, &config.mouve, "dry-run");
+    let mut env = config.env.clone().unwrap_or_default();
+    env.extend(metadata.clone());
+
+    let cache_key = artifacts_cache_key(&config, &commit);
+    let cache_enabled = config
+        .cache
+        .as_ref()
+        .and_then(|cache| cache.enabled)
+        .unwrap_or(true);
+    let cache_dir = format!("{}/{}", cache_dir, name);
+
+    let cached = cache_enabled
+        && has_artifacts(&cache_dir, &cache_key)
+        && restore_artifacts(&cache_dir, &cache_key, &repo_dir).is_ok();
+
+    match cached {
+        true => println!(
+            "\nBuild skipped, artifacts {} restored from the cache",
+            cache_key
+        ),
+        false => {
+            print_stage(
+                "pre_build",
+                &config.pre_build.clone().unwrap_or_default(),
+                &env,
+                &config,
+            );
+            print_stage("build", &config.build, &env, &config);
+
+            if !build {
+                println!("\nBuild not run, the sync plans below miss the files it would produce");
+                println!("pass --run-build to run it in the throwaway checkout");
+            } else if let Err(err) =
+                run_build(&config, &config_file_path, &repo_dir, &tmp_dir, &env)
+            {
+                println!("\n✖ {}", err);
+                let _ = fs::remove_dir_all(&tmp_dir);
+                return;
+            }
+        }
+    }
+
+    print_stage(
+        "pre_deploy",
+        &config.pre_deploy.clone().unwrap_or_default(),
+        &env,
+        &config,
+    );
+
+    let templates = config.templates.clone().unwrap_or_default();
+    if !templates.is_empty() {
+        println!("\n[templates]");
+        match template_vars(&config, &metadata)
+            .and_then(|vars| render_templates(&templates, &repo_dir, &vars))
+        {
+            Ok(rendered) => {
+                for (template, _, _) in rendered {
+                    let action = match Path::new(&template.to).exists() {
+                        true => "overwrite",
+                        false => "create",
+                    };
+                    println!("  {:<9} {} from {}", action, &template.to, &template.from);
+                }
+            }
+            Err(err) => println!("  ✖ {}", err),
+        }
+    }
+
+    print_sync_plans(&config, &repo_dir, &tmp_dir);
+
+    print_stage(
+        "post_deploy",
+        &config.post_deploy.clone().unwrap_or_default(),
+        &env,
+        &config,
+    );
+    print_stage(
+        "on_failure",
+        &config.on_failure.clone().unwrap_or_default(),
+        &env,
+        &config,
+    );
+
+    print_processes(&config, process_dir, name);
+
+    let _ = fs::remove_dir_all(&tmp_dir);
+}
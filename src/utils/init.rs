@@ -0,0 +1,367 @@
+use std::{fs, io, path::Path};
+
+use crate::core::utils::{
+    command::execute_commande, filesystem::read_from_file_ut, git::extract_repo_info,
+    remote::shell_quote,
+};
+
+use super::{
+    audit::{fail, set_outcome},
+    structs::{BuildStep, ConfigFile, FromTo, InitArgs},
+    validate::validate_config,
+};
+
+/// Build and deploy settings suggested from the files found at the repository root
+struct Detected {
+    kind: &'static str,
+    build: Vec<String>,
+    from: String,
+    exclude: Option<Vec<String>>,
+    entry_point: Option<String>,
+}
+
+/// Asks `question`, an empty answer keeps `default`. With `yes` the default is taken
+/// without asking
+fn ask(question: &str, default: &str, yes: bool) -> String {
+    if yes {
+        return default.to_string();
+    }
+
+    match default.len() {
+        0 => println!("{} : ", question),
+        _ => println!("{} [{}] : ", question, default),
+    }
+
+    let mut input = String::new();
+    let _ = io::stdin().read_line(&mut input);
+
+    match input.trim() {
+        "" => return default.to_string(),
+        answer => return answer.to_string(),
+    }
+}
+
+/// Branches of the remote and the one its HEAD points to
+fn list_branches(username: &str, folder_name: &str) -> Result<(Vec<String>, String), String> {
+    let output = execute_commande(&format!(
+        "git ls-remote --symref git@github.com:{}/{}.git",
+        username, folder_name
+    ))?;
+
+    let mut branches = Vec::<String>::new();
+    let mut head = String::new();
+
+    for line in output.lines() {
+        let (left, right) = match line.split_once('\t') {
+            Some(parts) => parts,
+            None => continue,
+        };
+
+        if let Some(target) = left.strip_prefix("ref: refs/heads/") {
+            if right == "HEAD" {
+                head = target.to_string();
+            }
+            continue;
+        }
+        if let Some(branch) = right.strip_prefix("refs/heads/") {
+            branches.push(branch.to_string());
+        }
+    }
+
+    if head.is_empty() {
+        head = branches.first().cloned().unwrap_or("main".to_string());
+    }
+
+    return Ok((branches, head));
+}
+
+fn cargo_package_name(content: &str) -> Option<String> {
+    let mut in_package = false;
+
+    for line in content.lines().map(|line| line.trim()) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        if !in_package {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=')
+            && key.trim() == "name"
+        {
+            return Some(value.trim().trim_matches('"').to_string());
+        }
+    }
+
+    return None;
+}
+
+fn detect_project(repo_dir: &str, folder_name: &str) -> Detected {
+    let file = |name: &str| read_from_file_ut(&format!("{}/{}", repo_dir, name)).ok();
+    let exists = |name: &str| Path::new(&format!("{}/{}", repo_dir, name)).exists();
+
+    if let Some(cargo) = file("Cargo.toml") {
+        let bin = cargo_package_name(&cargo).unwrap_or(folder_name.to_string());
+        return Detected {
+            kind: "Rust (Cargo.toml)",
+            build: vec!["cargo build --release".to_string()],
+            from: format!("target/release/{}", &bin),
+            exclude: None,
+            entry_point: Some(bin),
+        };
+    }
+
+    if let Some(package) = file("package.json") {
+        let package: serde_json::Value = serde_json::from_str(&package).unwrap_or_default();
+        let main = package["main"]
+            .as_str()
+            .map(|main| main.trim_start_matches("./").to_string());
+
+        // a build script produces a bundle, otherwise the sources are the app
+        if package["scripts"]["build"].is_string() {
+            return Detected {
+                kind: "Node.js (package.json)",
+                build: vec!["npm ci".to_string(), "npm run build".to_string()],
+                from: "dist/".to_string(),
+                exclude: None,
+                entry_point: None,
+            };
+        }
+        return Detected {
+            kind: "Node.js (package.json)",
+            build: vec!["npm ci --omit=dev".to_string()],
+            from: "./".to_string(),
+            exclude: Some(vec![".git".to_string()]),
+            entry_point: Some(main.unwrap_or("index.js".to_string())),
+        };
+    }
+
+    if let Some(go_mod) = file("go.mod") {
+        let bin = go_mod
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("module "))
+            .and_then(|module| module.trim().split('/').next_back())
+            .unwrap_or(folder_name)
+            .to_string();
+        return Detected {
+            kind: "Go (go.mod)",
+            build: vec![format!("go build -o bin/{} .", &bin)],
+            from: format!("bin/{}", &bin),
+            exclude: None,
+            entry_point: Some(bin),
+        };
+    }
+
+    if exists("pyproject.toml") || exists("requirements.txt") {
+        let install = match exists("requirements.txt") {
+            true => "python3 -m pip install -r requirements.txt",
+            false => "python3 -m pip install .",
+        };
+        return Detected {
+            kind: "Python (pyproject.toml)",
+            build: vec![install.to_string()],
+            from: "./".to_string(),
+            exclude: Some(vec![".git".to_string(), "__pycache__".to_string()]),
+            entry_point: ["main.py", "app.py", "manage.py"]
+                .into_iter()
+                .find(|name| exists(name))
+                .map(|name| name.to_string()),
+        };
+    }
+
+    return Detected {
+        kind: "unknown",
+        build: vec![],
+        from: "./".to_string(),
+        exclude: Some(vec![".git".to_string()]),
+        entry_point: None,
+    };
+}
+
+/// Asks for everything a config needs, checking the answers on the way, and writes a
+/// validated config. With `--yes` every question takes its default
+pub fn init_wizard(config_dir_path: &str, args: InitArgs) -> () {
+    let yes = args.yes;
+
+    if yes && (args.name.is_none() || args.repo.is_none()) {
+        return fail("--yes needs at least --name and --repo".to_string());
+    }
+
+    let name = match args.name {
+        Some(name) => name,
+        None => ask("Name of the application", "", false),
+    };
+    if name.is_empty() || name.contains('.') || name.contains('/') {
+        return fail(format!(
+            "`{}` is not a valid name, it can't be empty or contain `.` or `/`",
+            &name
+        ));
+    }
+
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, &name);
+    if read_from_file_ut(&config_file_path).is_ok() {
+        return fail(format!(
+            "Error! the specified name already has a config check at {config_file_path}"
+        ));
+    }
+
+    // keep asking until the repository is reachable
+    let mut repo = args.repo.unwrap_or_default();
+    let (username, folder_name, branches, head) = loop {
+        if repo.is_empty() {
+            repo = ask(
+                "Repository url (https://github.com/user/repo.git)",
+                "",
+                false,
+            );
+            if repo.is_empty() {
+                return fail("a repository url is needed".to_string());
+            }
+        }
+
+        let checked = match extract_repo_info(&repo) {
+            Some((username, folder_name)) => {
+                list_branches(username, folder_name).map(|(branches, head)| {
+                    (
+                        username.to_string(),
+                        folder_name.to_string(),
+                        branches,
+                        head,
+                    )
+                })
+            }
+            None => Err(format!("`{}` is not a repository url", &repo)),
+        };
+
+        match checked {
+            Ok(checked) => break checked,
+            Err(err) => {
+                println!("✖ {}", err.trim());
+                if yes {
+                    return set_outcome("failed", Some(err.trim().to_string()));
+                }
+                repo = String::new();
+            }
+        }
+    };
+    println!("✔ {} is reachable", &repo);
+
+    let branch = match args.branch {
+        Some(branch) => branch,
+        None => {
+            if !yes {
+                println!("Branches :");
+                for (idx, branch) in branches.iter().enumerate() {
+                    println!("  {}) {}", idx + 1, branch);
+                }
+            }
+            let answer = ask("Branch to deploy (name or number)", &head, yes);
+            match answer.parse::<usize>() {
+                Ok(idx) if idx >= 1 && idx <= branches.len() => branches[idx - 1].clone(),
+                _ => answer,
+            }
+        }
+    };
+    if !branches.contains(&branch) {
+        return fail(format!(
+            "✖ branch `{}` does not exist on the remote",
+            &branch
+        ));
+    }
+
+    // a shallow checkout is enough to look at the project files
+    let tmp_dir = std::env::temp_dir()
+        .join(format!("fast_flow-init-{}-{}", &name, std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let repo_dir = format!("{}/{}", &tmp_dir, &folder_name);
+
+    let detected = match execute_commande(&format!(
+        "git clone --quiet --depth 1 --branch {} git@github.com:{}/{}.git {}",
+        shell_quote(&branch),
+        &username,
+        &folder_name,
+        shell_quote(&repo_dir)
+    )) {
+        Ok(_) => detect_project(&repo_dir, &folder_name),
+        Err(err) => {
+            println!("could not look at the project files : {}", err.trim());
+            detect_project(&repo_dir, &folder_name)
+        }
+    };
+    let _ = fs::remove_dir_all(&tmp_dir);
+
+    println!("Project type : {}", detected.kind);
+
+    let build = ask(
+        "Build command, run by one shell so `cd dir && make` works",
+        &detected.build.join(" && "),
+        yes,
+    );
+    let from = ask(
+        "Files to deploy, relative to the repository (a trailing / deploys the contents)",
+        &detected.from,
+        yes,
+    );
+
+    let to = match args.to {
+        Some(to) => to,
+        None => ask("Destination directory", &format!("/opt/{}", &name), yes),
+    };
+    if !to.starts_with('/') {
+        return fail("✖ the destination must be an absolute path".to_string());
+    }
+
+    let entry_point = match args.entry_point {
+        Some(entry) => entry,
+        None => ask(
+            "Entry point started by flow start, relative to the destination (empty for none)",
+            &detected.entry_point.clone().unwrap_or_default(),
+            yes,
+        ),
+    };
+
+    let config = ConfigFile {
+        repo: repo.clone(),
+        // a single step, splitting on `&&` would lose a `cd` for the commands after it
+        build: match build.trim() {
+            "" => vec![],
+            build => vec![BuildStep::Command(build.to_string())],
+        },
+        mouve: vec![FromTo {
+            from,
+            to: to.clone(),
+            exclude: detected.exclude,
+            ..Default::default()
+        }],
+        branch: Some(branch),
+        entry_point: match entry_point.len() {
+            0 => None,
+            _ => Some(vec![Some(format!(
+                "{}/{}",
+                to.trim_end_matches('/'),
+                entry_point.trim_start_matches('/')
+            ))]),
+        },
+        ..Default::default()
+    };
+
+    let content = serde_json::to_string_pretty(&config).unwrap();
+
+    if let Err(diagnostics) = validate_config(&content, false) {
+        println!("✖ the config is not valid, nothing was written :");
+        for diagnostic in diagnostics {
+            println!("    {}", diagnostic);
+        }
+        return set_outcome("failed", Some("invalid config".to_string()));
+    }
+
+    let _ = fs::create_dir_all(config_dir_path);
+    match fs::write(&config_file_path, content) {
+        Ok(_) => {
+            println!("✔ config written to {}", &config_file_path);
+            println!("start watching it with flow watch -n {}", &name);
+        }
+        Err(err) => fail(err.to_string()),
+    }
+}
@@ -0,0 +1,189 @@
+use std::{fmt::Arguments, sync::Mutex};
+
+use chrono::SecondsFormat;
+use fern::Dispatch;
+use log::{
+    Record,
+    kv::{Error, Key, Value, VisitSource, VisitValue},
+};
+use serde_json::{Map, Value as Json};
+
+use crate::core::utils::filesystem::load_file_parsed;
+
+use super::structs::{ConfigFile, LogFormat, StepTag};
+
+/// Configuration and run every record is tagged with
+struct LogContext {
+    config: Option<String>,
+    run: Option<String>,
+}
+
+static CONTEXT: Mutex<LogContext> = Mutex::new(LogContext {
+    config: None,
+    run: None,
+});
+
+/// Tags the records that follow with the configuration being watched or deployed
+pub fn set_log_config(name: &str) -> () {
+    CONTEXT.lock().unwrap().config = Some(name.to_string());
+}
+
+/// Tags the records that follow with the id of the run in progress
+pub fn set_log_run(run: Option<&str>) -> () {
+    CONTEXT.lock().unwrap().run = run.map(|run| run.to_string());
+}
+
+/// Log format asked for by the `logs` section of a config, text when it has none
+pub fn log_format(config_file_path: &str) -> LogFormat {
+    return load_file_parsed::<ConfigFile>(config_file_path)
+        .ok()
+        .and_then(|config| config.logs)
+        .and_then(|logs| logs.format)
+        .unwrap_or_default();
+}
+
+struct JsonValue(Json);
+
+impl<'v> VisitValue<'v> for JsonValue {
+    fn visit_any(&mut self, value: Value) -> Result<(), Error> {
+        self.0 = Json::String(value.to_string());
+        return Ok(());
+    }
+
+    fn visit_null(&mut self) -> Result<(), Error> {
+        self.0 = Json::Null;
+        return Ok(());
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), Error> {
+        self.0 = Json::from(value);
+        return Ok(());
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), Error> {
+        self.0 = Json::from(value);
+        return Ok(());
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), Error> {
+        self.0 = Json::from(value);
+        return Ok(());
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), Error> {
+        self.0 = Json::Bool(value);
+        return Ok(());
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), Error> {
+        self.0 = Json::String(value.to_string());
+        return Ok(());
+    }
+}
+
+/// Key-values given to the log macros, such as `info!(stage = "build"; ...)`
+struct Fields(Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let mut json = JsonValue(Json::Null);
+        value.visit(&mut json)?;
+        self.0.insert(key.as_str().to_string(), json.0);
+        return Ok(());
+    }
+}
+
+fn field(fields: &Map<String, Json>, key: &str) -> Option<String> {
+    match fields.get(key) {
+        Some(Json::String(value)) => return Some(value.clone()),
+        Some(Json::Null) | None => return None,
+        Some(value) => return Some(value.to_string()),
+    }
+}
+
+/// The `[build step 1]`, `[host web]` and `[stdout]` tags the text format puts before
+/// the message
+fn text_tags(fields: &Map<String, Json>) -> String {
+    let mut tags = String::new();
+
+    match (
+        fields.get("step").and_then(Json::as_u64),
+        field(fields, "host"),
+    ) {
+        (Some(step), host) => {
+            let tag = StepTag {
+                stage: field(fields, "stage").unwrap_or_default(),
+                step: step as usize,
+                name: field(fields, "step_name"),
+                host,
+            };
+            tags.push_str(&format!("[{}]", tag.label()));
+        }
+        (None, Some(host)) => tags.push_str(&format!("[host {}]", host)),
+        (None, None) => {}
+    }
+
+    if let Some(stream) = field(fields, "stream") {
+        tags.push_str(&format!("[{}]", stream));
+    }
+
+    if !tags.is_empty() {
+        tags.push(' ');
+    }
+    return tags;
+}
+
+pub fn format_record(format: LogFormat, message: &Arguments, record: &Record) -> String {
+    let mut fields = Fields(Map::new());
+    let _ = record.key_values().visit(&mut fields);
+    let mut fields = fields.0;
+
+    match format {
+        LogFormat::Text => {
+            return format!(
+                "[{}][{}] {}{}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                text_tags(&fields),
+                message
+            );
+        }
+        LogFormat::Json => {
+            let (config, run) = {
+                let context = CONTEXT.lock().unwrap();
+                (context.config.clone(), context.run.clone())
+            };
+
+            let mut json = Map::new();
+            json.insert(
+                "timestamp".to_string(),
+                Json::from(chrono::Local::now().to_rfc3339_opts(SecondsFormat::Millis, false)),
+            );
+            json.insert("level".to_string(), Json::from(record.level().as_str()));
+            json.insert("config".to_string(), Json::from(config));
+            json.insert("run".to_string(), Json::from(run));
+            json.insert(
+                "stage".to_string(),
+                fields.remove("stage").unwrap_or(Json::Null),
+            );
+            json.insert(
+                "step".to_string(),
+                fields.remove("step").unwrap_or(Json::Null),
+            );
+            json.insert("message".to_string(), Json::from(message.to_string()));
+            // the remaining fields the record has, such as the step name, host or stream
+            json.extend(fields.into_iter().filter(|(_, value)| !value.is_null()));
+
+            return Json::Object(json).to_string();
+        }
+    }
+}
+
+/// Logger writing the records in `format`, chained to wherever they should go
+pub fn logger(format: LogFormat) -> Dispatch {
+    return Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!("{}", format_record(format, message, record)))
+        })
+        .level(log::LevelFilter::Debug);
+}
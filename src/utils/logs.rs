@@ -0,0 +1,497 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration as StdDuration, Instant},
+};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use console::style;
+use inotify::{EventMask, Inotify, WatchMask};
+use log::Level;
+use serde_json::Value;
+
+use crate::core::utils::rotate::rotated_files;
+
+use super::{
+    output::format_records,
+    structs::{LogArgs, LogLine, OutputFormat},
+};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Takes the raw and parsed lines read from a log, gives false to stop reading. While a
+/// followed log stays quiet it gets no lines every `FOLLOW_IDLE`, so a sink writing to
+/// a client can notice it left
+pub type LineSink<'a> = dyn FnMut(&[(String, LogLine)]) -> bool + 'a;
+
+/// Longest a follow goes without handing anything to its sink
+const FOLLOW_IDLE: StdDuration = StdDuration::from_secs(15);
+
+/// Reads a line of the JSON log format, None when it isn't one
+fn parse_json_line(line: &str) -> Option<LogLine> {
+    let json = match serde_json::from_str::<Value>(line) {
+        Ok(Value::Object(json)) => json,
+        _ => return None,
+    };
+    let field = |key: &str| json.get(key).and_then(Value::as_str).map(|v| v.to_string());
+
+    return Some(LogLine {
+        time: field("timestamp"),
+        level: field("level"),
+        run: field("run"),
+        stage: field("stage"),
+        step: json.get("step").and_then(Value::as_u64),
+        message: field("message")?,
+    });
+}
+
+/// Splits a `[time][LEVEL] message` line written by the watcher logger, or a JSON line
+/// when the config logs in JSON
+pub fn parse_log_line(line: &str) -> LogLine {
+    if line.starts_with('{')
+        && let Some(parsed) = parse_json_line(line)
+    {
+        return parsed;
+    }
+
+    let parsed = line.strip_prefix('[').and_then(|rest| {
+        let (time, rest) = rest.split_once("][")?;
+        let (level, message) = rest.split_once(']')?;
+        Some(LogLine {
+            time: Some(time.to_string()),
+            level: Some(level.to_string()),
+            run: None,
+            stage: None,
+            step: None,
+            message: message.trim_start().to_string(),
+        })
+    });
+
+    match parsed {
+        Some(parsed) => return parsed,
+        None => {
+            return LogLine {
+                time: None,
+                level: None,
+                run: None,
+                stage: None,
+                step: None,
+                message: line.to_string(),
+            };
+        }
+    }
+}
+
+/// Time of a log line, written in the text format or as RFC 3339 in JSON lines
+fn parse_line_time(time: &str) -> Option<NaiveDateTime> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(time, TIME_FORMAT) {
+        return Some(time);
+    }
+    return chrono::DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.with_timezone(&chrono::Local).naive_local());
+}
+
+/// `30s`, `15m`, `1h`, `2d` and `1w` count back from now, anything else is read as a date
+pub fn parse_since(since: &str) -> Result<NaiveDateTime, String> {
+    let now = chrono::Local::now().naive_local();
+    let since = since.trim();
+
+    let split = since
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(since.len());
+    if let Ok(amount) = since[..split].parse::<i64>() {
+        let ago = match &since[split..] {
+            "s" => Some(Duration::seconds(amount)),
+            "m" => Some(Duration::minutes(amount)),
+            "h" => Some(Duration::hours(amount)),
+            "d" => Some(Duration::days(amount)),
+            "w" => Some(Duration::weeks(amount)),
+            _ => None,
+        };
+        if let Some(ago) = ago {
+            return Ok(now - ago);
+        }
+    }
+
+    if let Ok(time) = NaiveDateTime::parse_from_str(since, TIME_FORMAT) {
+        return Ok(time);
+    }
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&chrono::Local).naive_local());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap());
+    }
+
+    return Err(format!(
+        "`{}` is neither a duration such as 30m, 1h or 2d nor a date such as 2024-05-01 12:00:00",
+        since
+    ));
+}
+
+/// Keeps the lines asked for with `--since`, `--level` and `--run`. Lines without a
+/// `[time][LEVEL]` header continue the line above them and share its fate
+struct LineFilter {
+    since: Option<NaiveDateTime>,
+    level: Option<Level>,
+    run: Option<String>,
+    time: Option<NaiveDateTime>,
+    line_level: Option<Level>,
+    in_run: bool,
+}
+
+impl LineFilter {
+    fn keep(&mut self, line: &LogLine) -> bool {
+        if let Some(time) = &line.time {
+            self.time = parse_line_time(time);
+            self.line_level = line.level.as_ref().and_then(|level| level.parse().ok());
+        }
+
+        if let Some(run) = &self.run {
+            // a run starts after its poll read the config and lasts until the next poll,
+            // JSON lines say which run they belong to
+            if let Some(id) = &line.run {
+                self.in_run = id == run;
+            } else if let Some(id) = line.message.strip_prefix("Starting run ") {
+                self.in_run = id.trim() == run;
+            } else if line.time.is_some() && line.message == "Reading config" {
+                self.in_run = false;
+            }
+            if !self.in_run {
+                return false;
+            }
+        }
+
+        if let Some(since) = self.since
+            && self.time.is_none_or(|time| time < since)
+        {
+            return false;
+        }
+
+        if let Some(level) = self.level
+            && self.line_level.is_none_or(|line_level| line_level > level)
+        {
+            return false;
+        }
+
+        return true;
+    }
+}
+
+/// Colours warnings and errors, console leaves the line as is when stdout isn't a terminal
+fn paint(line: &str, parsed: &LogLine) -> String {
+    match parsed.level.as_deref() {
+        Some("ERROR") => return style(line).red().to_string(),
+        Some("WARN") => return style(line).yellow().to_string(),
+        _ => return line.to_string(),
+    }
+}
+
+/// Lines of `bytes` kept by `filter`, an unfinished last line stays in `partial` for the
+/// next read
+fn split_chunk(
+    bytes: &[u8],
+    partial: &mut Vec<u8>,
+    filter: &mut LineFilter,
+) -> Vec<(String, LogLine)> {
+    partial.extend_from_slice(bytes);
+
+    let end = match partial.iter().rposition(|byte| *byte == b'\n') {
+        Some(end) => end,
+        None => return Vec::new(),
+    };
+    let complete: Vec<u8> = partial.drain(..=end).collect();
+
+    return String::from_utf8_lossy(&complete)
+        .lines()
+        .map(|line| (line.to_string(), parse_log_line(line)))
+        .filter(|(_, parsed)| filter.keep(parsed))
+        .collect();
+}
+
+/// Waits up to `timeout_ms` for events on `inotify`, false when none came
+fn wait_events(inotify: &Inotify, timeout_ms: i32) -> Result<bool, String> {
+    let mut fds = libc::pollfd {
+        fd: inotify.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    match unsafe { libc::poll(&mut fds, 1, timeout_ms) } {
+        -1 => {
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                ErrorKind::Interrupted => return Ok(false),
+                _ => return Err(err.to_string()),
+            }
+        }
+        0 => return Ok(false),
+        _ => return Ok(true),
+    }
+}
+
+/// Hands what gets appended to `path` from `file`'s position on to `emit`, reopening the
+/// file when it is recreated or rotated and starting over when it is truncated. Stops once
+/// `emit` gives false, such as when its reader is gone
+fn follow(
+    path: &Path,
+    mut file: Option<File>,
+    mut partial: Vec<u8>,
+    inotify: &mut Inotify,
+    filter: &mut LineFilter,
+    emit: &mut LineSink,
+) -> Result<(), String> {
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let mut buffer = [0; 4096];
+    let mut last_emit = Instant::now();
+
+    loop {
+        let mut reopen = false;
+
+        // the other logs of the directory wake this up too, only a quiet sink is checked on
+        let idle = FOLLOW_IDLE.saturating_sub(last_emit.elapsed());
+        if wait_events(inotify, idle.as_millis() as i32)? {
+            let events = match inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err.to_string()),
+            };
+            for event in events {
+                if event.name.map(|name| name.to_os_string()) != file_name {
+                    continue;
+                }
+                if event
+                    .mask
+                    .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                {
+                    reopen = true;
+                }
+            }
+        }
+
+        if reopen || file.is_none() {
+            file = File::open(path).ok();
+            partial.clear();
+        }
+
+        let mut lines = Vec::new();
+        if let Some(file) = file.as_mut() {
+            let len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+            if len < file.stream_position().unwrap_or(0) {
+                let _ = file.seek(SeekFrom::Start(0));
+                partial.clear();
+            }
+
+            let mut bytes = Vec::new();
+            if let Err(err) = file.read_to_end(&mut bytes) {
+                return Err(err.to_string());
+            }
+            lines = split_chunk(&bytes, &mut partial, filter);
+        }
+
+        // a reader that left is noticed even on a quiet log, instead of holding the
+        // thread and the inotify instance forever
+        if !lines.is_empty() || last_emit.elapsed() >= FOLLOW_IDLE {
+            if !emit(&lines) {
+                return Ok(());
+            }
+            last_emit = Instant::now();
+        }
+    }
+}
+
+/// Reads the lines of `reader` kept by `filter` into `lines`, holding the last `tail` of
+/// them. Given `partial`, an unfinished last line is left there rather than read
+fn collect_lines(
+    reader: &mut impl BufRead,
+    filter: &mut LineFilter,
+    tail: Option<usize>,
+    lines: &mut VecDeque<(String, LogLine)>,
+    partial: Option<&mut Vec<u8>>,
+) -> io::Result<()> {
+    let mut raw = Vec::new();
+
+    loop {
+        raw.clear();
+        if reader.read_until(b'\n', &mut raw)? == 0 {
+            break;
+        }
+
+        if raw.last() != Some(&b'\n')
+            && let Some(partial) = partial
+        {
+            *partial = raw;
+            break;
+        }
+
+        let line = String::from_utf8_lossy(&raw)
+            .trim_end_matches(['\n', '\r'])
+            .to_string();
+        let parsed = parse_log_line(&line);
+        if !filter.keep(&parsed) {
+            continue;
+        }
+
+        lines.push_back((line, parsed));
+        if tail.is_some_and(|tail| lines.len() > tail) {
+            lines.pop_front();
+        }
+    }
+
+    return Ok(());
+}
+
+/// Hands the lines of the log of `args` kept by its filters to `emit`, the rotated files
+/// first. With `follow`, the lines appended afterwards are handed over as they come
+pub fn stream_logs(logs_dir: &str, args: &LogArgs, emit: &mut LineSink) -> Result<(), String> {
+    let stream = match args.process {
+        true => "process",
+        false => "watch",
+    };
+    let log_file_path = format!("{}/{}.{}.log", logs_dir, &args.name, stream);
+    let path = Path::new(&log_file_path);
+
+    let since = args.since.as_deref().map(parse_since).transpose()?;
+
+    let mut filter = LineFilter {
+        since,
+        level: args.level,
+        run: args.run.clone(),
+        time: None,
+        line_level: None,
+        in_run: false,
+    };
+
+    // watch before reading so nothing written in between is missed
+    let mut inotify = None;
+    if args.follow {
+        let watched = Inotify::init().and_then(|inotify| {
+            inotify.watches().add(
+                path.parent().unwrap_or(Path::new(".")),
+                WatchMask::MODIFY | WatchMask::CREATE | WatchMask::MOVED_TO,
+            )?;
+            Ok(inotify)
+        });
+        match watched {
+            Ok(watched) => inotify = Some(watched),
+            Err(err) => return Err(format!("could not follow {} : {}", &log_file_path, err)),
+        }
+    }
+
+    let rotated = rotated_files(&log_file_path);
+
+    let file = match File::open(path) {
+        Ok(file) => Some(file),
+        Err(_) if args.follow => {
+            eprintln!("waiting for {} to be created", &log_file_path);
+            None
+        }
+        Err(_) if !rotated.is_empty() => None,
+        Err(err) => return Err(format!("{} : {}", &log_file_path, err)),
+    };
+
+    let tail = match (args.tail, args.follow) {
+        (Some(tail), _) => Some(tail),
+        (None, true) => Some(10),
+        (None, false) => None,
+    };
+
+    let mut lines = VecDeque::<(String, LogLine)>::new();
+    let mut partial = Vec::<u8>::new();
+
+    // rotated files first, oldest to newest, then the live log
+    for rotated in rotated {
+        let res = match rotated.ends_with(".gz") {
+            true => Command::new("gzip")
+                .arg("-dc")
+                .arg(&rotated)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .and_then(|mut child| {
+                    let stdout = child.stdout.take().unwrap();
+                    let res = collect_lines(
+                        &mut BufReader::new(stdout),
+                        &mut filter,
+                        tail,
+                        &mut lines,
+                        None,
+                    );
+                    let _ = child.wait();
+                    res
+                }),
+            false => File::open(&rotated).and_then(|file| {
+                collect_lines(
+                    &mut BufReader::new(file),
+                    &mut filter,
+                    tail,
+                    &mut lines,
+                    None,
+                )
+            }),
+        };
+        if let Err(err) = res {
+            println!("{} : {}", &rotated, err);
+        }
+    }
+
+    let mut reader = file.map(BufReader::new);
+    if let Some(reader) = reader.as_mut() {
+        // a line still being written is finished by the follow loop
+        let partial = match args.follow {
+            true => Some(&mut partial),
+            false => None,
+        };
+        if let Err(err) = collect_lines(reader, &mut filter, tail, &mut lines, partial) {
+            return Err(format!("{} : {}", &log_file_path, err));
+        }
+    }
+
+    if !emit(lines.make_contiguous()) {
+        return Ok(());
+    }
+
+    if let Some(mut inotify) = inotify {
+        let file = reader.map(|reader| reader.into_inner());
+        return follow(path, file, partial, &mut inotify, &mut filter, emit);
+    }
+
+    return Ok(());
+}
+
+pub fn show_logs(logs_dir: &str, mut args: LogArgs) -> () {
+    if args.output != OutputFormat::Table {
+        // records are printed as one document, so there is nothing to follow
+        args.follow = false;
+
+        let mut records = Vec::<LogLine>::new();
+        let res = stream_logs(logs_dir, &args, &mut |lines| {
+            records.extend(lines.iter().map(|(_, parsed)| parsed.clone()));
+            true
+        });
+        match res {
+            Ok(_) => println!("{}", format_records(&records, args.output)),
+            Err(err) => println!("{}", err),
+        }
+        return;
+    }
+
+    // gives false once stdout is closed, such as when piped into `head`
+    let res = stream_logs(logs_dir, &args, &mut |lines| {
+        let mut out = io::stdout().lock();
+        for (line, parsed) in lines {
+            if writeln!(out, "{}", paint(line, parsed)).is_err() {
+                return false;
+            }
+        }
+        out.flush().is_ok()
+    });
+    if let Err(err) = res {
+        println!("{}", err);
+    }
+}
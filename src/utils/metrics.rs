@@ -0,0 +1,343 @@
+use std::{fs, net::TcpListener, process::exit};
+
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::core::utils::{
+    filesystem::{list_dir_contents, load_file_parsed, read_from_file_ut},
+    sampler::sample_usage,
+};
+
+use super::{
+    daemon::serve_in_background,
+    state::load_state,
+    structs::{AppState, ConfigFile, ProcessUsage},
+    subcommands::process_pids,
+};
+
+/// Upper bounds in seconds of the build duration histogram buckets
+pub const BUILD_DURATION_BUCKETS: [f64; 9] =
+    [5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 3600.0];
+
+const METRICS_PID: &str = "fast_flow.metrics.pid";
+const METRICS_LOG: &str = "fast_flow.metrics.log";
+
+/// What is exported about a configuration
+struct AppMetrics {
+    name: String,
+    version: Option<String>,
+    branch: String,
+    state: AppState,
+    watcher_up: bool,
+    app_up: bool,
+    /// Usage of the entry points along with their children
+    usage: ProcessUsage,
+}
+
+fn is_running(pid: &str) -> bool {
+    return !pid.is_empty() && fs::metadata(format!("/proc/{}", pid)).is_ok();
+}
+
+fn collect_metrics(state_dir: &str, process_dir: &str, config_dir_path: &str) -> Vec<AppMetrics> {
+    let mut apps = Vec::<AppMetrics>::new();
+    let mut roots = Vec::<Vec<u32>>::new();
+
+    for file_name in list_dir_contents(config_dir_path).unwrap_or_default() {
+        let name = match file_name.strip_suffix(".config.json") {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let config =
+            match load_file_parsed::<ConfigFile>(&format!("{}/{}", config_dir_path, &file_name)) {
+                Ok(config) => config,
+                Err(_) => continue,
+            };
+
+        let watcher_pid =
+            read_from_file_ut(&format!("{}/{}.watch.pid", process_dir, &name)).unwrap_or_default();
+        let app_pids: Vec<u32> = process_pids(process_dir, &name)
+            .into_iter()
+            .filter(|pid| is_running(&pid.to_string()))
+            .collect();
+
+        apps.push(AppMetrics {
+            state: load_state(state_dir, &name),
+            name,
+            version: config.version,
+            branch: config.branch.unwrap_or("main".to_string()),
+            watcher_up: is_running(watcher_pid.trim()),
+            app_up: !app_pids.is_empty(),
+            usage: ProcessUsage::default(),
+        });
+        roots.push(app_pids);
+    }
+
+    for (app, usage) in apps.iter_mut().zip(sample_usage(&roots)) {
+        app.usage = usage.unwrap_or_default();
+    }
+
+    return apps;
+}
+
+/// Quotes a label value of the exposition format
+fn label(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    return format!("\"{}\"", escaped);
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[String]) -> () {
+    out.push_str(&format!("# HELP {} {}\n", name, help));
+    out.push_str(&format!("# TYPE {} {}\n", name, kind));
+    for sample in samples {
+        out.push_str(sample);
+        out.push('\n');
+    }
+}
+
+/// Metrics of every configuration in the Prometheus text exposition format
+pub fn render_metrics(state_dir: &str, process_dir: &str, config_dir_path: &str) -> String {
+    let apps = collect_metrics(state_dir, process_dir, config_dir_path);
+    let mut out = String::new();
+
+    let per_app = |metric: &str, value: &dyn Fn(&AppMetrics) -> Option<String>| -> Vec<String> {
+        return apps
+            .iter()
+            .filter_map(|app| {
+                let value = value(app)?;
+                Some(format!(
+                    "{}{{config={}}} {}",
+                    metric,
+                    label(&app.name),
+                    value
+                ))
+            })
+            .collect();
+    };
+
+    let mut deploys = Vec::<String>::new();
+    for app in &apps {
+        for (result, count) in [
+            ("success", app.state.metrics.deploys_succeeded),
+            ("failed", app.state.metrics.deploys_failed),
+        ] {
+            deploys.push(format!(
+                "fast_flow_deploys_total{{config={},result={}}} {}",
+                label(&app.name),
+                label(result),
+                count
+            ));
+        }
+    }
+    write_family(
+        &mut out,
+        "fast_flow_deploys_total",
+        "counter",
+        "Finished deploys by result.",
+        &deploys,
+    );
+
+    let mut builds = Vec::<String>::new();
+    for app in &apps {
+        let metrics = &app.state.metrics;
+        let config = label(&app.name);
+
+        let mut cumulative = 0;
+        for (idx, bound) in BUILD_DURATION_BUCKETS.iter().enumerate() {
+            cumulative += metrics.build_buckets.get(idx).copied().unwrap_or(0);
+            builds.push(format!(
+                "fast_flow_build_duration_seconds_bucket{{config={},le={}}} {}",
+                &config,
+                label(&bound.to_string()),
+                cumulative
+            ));
+        }
+        builds.push(format!(
+            "fast_flow_build_duration_seconds_bucket{{config={},le=\"+Inf\"}} {}",
+            &config, metrics.build_count
+        ));
+        builds.push(format!(
+            "fast_flow_build_duration_seconds_sum{{config={}}} {}",
+            &config, metrics.build_seconds_sum
+        ));
+        builds.push(format!(
+            "fast_flow_build_duration_seconds_count{{config={}}} {}",
+            &config, metrics.build_count
+        ));
+    }
+    write_family(
+        &mut out,
+        "fast_flow_build_duration_seconds",
+        "histogram",
+        "Time the pre_build and build stages took, for the builds that passed.",
+        &builds,
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_last_success_timestamp_seconds",
+        "gauge",
+        "Unix time of the last successful deploy.",
+        &per_app("fast_flow_last_success_timestamp_seconds", &|app| {
+            app.state.metrics.last_success_at.map(|at| at.to_string())
+        }),
+    );
+
+    let deployed: Vec<String> = apps
+        .iter()
+        .filter_map(|app| {
+            let version = app.version.as_ref()?;
+            Some(format!(
+                "fast_flow_deployed_info{{config={},commit={},branch={}}} 1",
+                label(&app.name),
+                label(version),
+                label(&app.branch)
+            ))
+        })
+        .collect();
+    write_family(
+        &mut out,
+        "fast_flow_deployed_info",
+        "gauge",
+        "Commit currently deployed, as labels.",
+        &deployed,
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_poll_errors_total",
+        "counter",
+        "Polls that failed before a run could start.",
+        &per_app("fast_flow_poll_errors_total", &|app| {
+            Some(app.state.metrics.poll_errors.to_string())
+        }),
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_watcher_up",
+        "gauge",
+        "Whether the watcher of the configuration is running.",
+        &per_app("fast_flow_watcher_up", &|app| {
+            Some((app.watcher_up as u8).to_string())
+        }),
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_app_up",
+        "gauge",
+        "Whether an entry point started by flow start is running.",
+        &per_app("fast_flow_app_up", &|app| {
+            Some((app.app_up as u8).to_string())
+        }),
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_restarts_total",
+        "counter",
+        "Restarts of the entry points by flow start.",
+        &per_app("fast_flow_restarts_total", &|app| {
+            Some(app.state.restarts.to_string())
+        }),
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_app_cpu_percent",
+        "gauge",
+        "Cpu usage of the running entry points and their children in percent.",
+        &per_app("fast_flow_app_cpu_percent", &|app| {
+            app.app_up.then(|| app.usage.cpu_percent.to_string())
+        }),
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_app_memory_bytes",
+        "gauge",
+        "Resident memory of the running entry points and their children.",
+        &per_app("fast_flow_app_memory_bytes", &|app| {
+            app.app_up.then(|| app.usage.memory_bytes.to_string())
+        }),
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_app_threads",
+        "gauge",
+        "Threads of the running entry points and their children.",
+        &per_app("fast_flow_app_threads", &|app| {
+            app.app_up.then(|| app.usage.threads.to_string())
+        }),
+    );
+
+    write_family(
+        &mut out,
+        "fast_flow_app_open_files",
+        "gauge",
+        "Open file descriptors of the running entry points and their children.",
+        &per_app("fast_flow_app_open_files", &|app| {
+            app.app_up.then(|| app.usage.open_files.to_string())
+        }),
+    );
+
+    return out;
+}
+
+fn serve_metrics(
+    listener: TcpListener,
+    state_dir: &str,
+    process_dir: &str,
+    config_dir_path: &str,
+) -> () {
+    let server = match Server::from_listener(listener, None) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Failed to start the metrics endpoint : {}", err);
+            return;
+        }
+    };
+    let content_type =
+        Header::from_bytes("Content-Type", "text/plain; version=0.0.4; charset=utf-8").unwrap();
+
+    for request in server.incoming_requests() {
+        let path = request.url().split('?').next().unwrap_or("").to_string();
+
+        let res = match (request.method(), path.as_str()) {
+            (Method::Get, "/metrics") => {
+                let body = render_metrics(state_dir, process_dir, config_dir_path);
+                request.respond(Response::from_string(body).with_header(content_type.clone()))
+            }
+            _ => request.respond(Response::from_string("not found\n").with_status_code(404)),
+        };
+        if let Err(err) = res {
+            eprintln!("Failed to answer a metrics request : {}", err);
+        }
+    }
+}
+
+/// Starts the daemon serving `/metrics` on `listen`, unless one is already running
+pub fn start_metrics_daemon(
+    listen: &str,
+    state_dir: &str,
+    process_dir: &str,
+    logs_dir: &str,
+    config_dir_path: &str,
+) -> () {
+    let pid_file_path = format!("{}/{}", process_dir, METRICS_PID);
+    let log_file_path = format!("{}/{}", logs_dir, METRICS_LOG);
+
+    if let Some(listener) = serve_in_background(
+        "metrics endpoint",
+        listen,
+        "/metrics",
+        &pid_file_path,
+        &log_file_path,
+    ) {
+        serve_metrics(listener, state_dir, process_dir, config_dir_path);
+        exit(0);
+    }
+}
@@ -1,6 +1,24 @@
+pub mod audit;
 pub mod content;
 pub mod daemon;
+pub mod dry_run;
+pub mod init;
+pub mod logger;
+pub mod logs;
+pub mod metrics;
+pub mod notify;
+pub mod output;
+pub mod paths;
+pub mod server;
+pub mod stages;
+pub mod state;
 pub mod structs;
 pub mod subcommands;
 pub mod table;
+pub mod top;
+#[allow(
+    clippy::module_inception,
+    reason = "the shared helpers predate the module layout"
+)]
 pub mod utils;
+pub mod validate;
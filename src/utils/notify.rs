@@ -0,0 +1,662 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{Read, Write},
+    os::unix::{fs::OpenOptionsExt, process::CommandExt},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use crate::core::utils::{command::kill_process_group, template::render_template};
+
+use super::{
+    paths::load_global_config,
+    stages::StageContext,
+    structs::{NotifierConfig, NotifierKind, NotifyEvent},
+    utils::{DEFAULT_NOTIFY_RETRIES, DEFAULT_NOTIFY_RETRY_DELAY},
+};
+
+/// Seconds a notifier request may take before it counts as failed
+const REQUEST_TIMEOUT: u64 = 10;
+
+/// An event the notifiers are told about
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: NotifyEvent,
+    pub config: String,
+    pub run_id: Option<String>,
+    pub commit: Option<String>,
+    pub branch: Option<String>,
+    pub stage: Option<String>,
+    pub error: Option<String>,
+    pub message: String,
+}
+
+impl Notification {
+    /// An event of the run of `ctx`, `failed` holds the failing stage and error
+    pub fn for_run(event: NotifyEvent, ctx: &StageContext, failed: Option<(&str, &str)>) -> Self {
+        let meta = |key: &str| ctx.env.get(key).cloned();
+        let commit = meta("FLOW_COMMIT_SHA");
+        let short = commit
+            .as_deref()
+            .map(|sha| sha.chars().take(7).collect::<String>())
+            .unwrap_or_default();
+
+        let message = match (event, failed) {
+            (NotifyEvent::DeployStarted, _) => {
+                format!("deploy of [{}] at {} started", &ctx.name, short)
+            }
+            (_, Some((stage, err))) => format!(
+                "deploy of [{}] at {} failed in {} : {}",
+                &ctx.name, short, stage, err
+            ),
+            _ => format!("deploy of [{}] at {} succeeded", &ctx.name, short),
+        };
+
+        return Notification {
+            event,
+            config: ctx.name.clone(),
+            run_id: meta("FLOW_RUN_ID"),
+            commit,
+            branch: meta("FLOW_BRANCH"),
+            stage: failed.map(|(stage, _)| stage.to_string()),
+            error: failed.map(|(_, err)| err.to_string()),
+            message,
+        };
+    }
+
+    /// An event of the entry points of `config`
+    pub fn for_app(event: NotifyEvent, config: &str, message: String) -> Self {
+        return Notification {
+            event,
+            config: config.to_string(),
+            run_id: None,
+            commit: None,
+            branch: None,
+            stage: None,
+            error: None,
+            message,
+        };
+    }
+
+    fn event_name(&self) -> String {
+        return serde_json::to_value(self.event)
+            .ok()
+            .and_then(|event| event.as_str().map(|event| event.to_string()))
+            .unwrap_or_default();
+    }
+
+    /// Values of the `body` templates, every one of them is set so a template never fails
+    /// on an event that lacks one
+    fn vars(&self) -> HashMap<String, String> {
+        let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+
+        return [
+            ("event", Some(self.event_name())),
+            ("config", Some(self.config.clone())),
+            ("run_id", self.run_id.clone()),
+            ("commit", self.commit.clone()),
+            ("branch", self.branch.clone()),
+            ("stage", self.stage.clone()),
+            ("error", self.error.clone()),
+            ("message", Some(self.message.clone())),
+            ("time", Some(chrono::Local::now().to_rfc3339())),
+            ("hostname", Some(hostname.trim().to_string())),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.unwrap_or_default()))
+        .collect();
+    }
+}
+
+/// Notifications still being sent
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// The global notifiers followed by the ones of a config
+pub fn notifiers_for(notify: Option<Vec<NotifierConfig>>) -> Vec<NotifierConfig> {
+    let mut notifiers = load_global_config().notify.unwrap_or_default();
+    notifiers.extend(notify.unwrap_or_default());
+    return notifiers;
+}
+
+/// Sends `notification` in the background with every notifier subscribed to its event
+pub fn notify(notifiers: &[NotifierConfig], notification: Notification) -> () {
+    let notification = Arc::new(notification);
+    let mut pending = PENDING.lock().unwrap();
+    pending.retain(|handle| !handle.is_finished());
+
+    for notifier in notifiers {
+        let subscribed = notifier
+            .events
+            .as_ref()
+            .is_none_or(|events| events.contains(&notification.event));
+        if !subscribed {
+            continue;
+        }
+
+        let notifier = notifier.clone();
+        let notification = notification.clone();
+        pending.push(thread::spawn(move || {
+            send_with_retries(&notifier, &notification)
+        }));
+    }
+}
+
+/// Waits for the notifications still being sent, for commands about to exit
+pub fn wait_notifications() -> () {
+    let pending: Vec<JoinHandle<()>> = PENDING.lock().unwrap().drain(..).collect();
+    for handle in pending {
+        let _ = handle.join();
+    }
+}
+
+fn send_with_retries(notifier: &NotifierConfig, notification: &Notification) -> () {
+    let retries = notifier.retries.unwrap_or(DEFAULT_NOTIFY_RETRIES);
+    let mut delay = notifier.retry_delay.unwrap_or(DEFAULT_NOTIFY_RETRY_DELAY);
+    let kind = serde_json::to_value(notifier.kind).unwrap_or_default();
+
+    for attempt in 0..=retries {
+        match send(notifier, notification) {
+            Ok(_) => return,
+            Err(err) if attempt < retries => {
+                warn!(
+                    "{} notifier failed, retrying in {}s : {}",
+                    kind.as_str().unwrap_or(""),
+                    delay,
+                    err
+                );
+                thread::sleep(Duration::from_secs(delay));
+                delay *= 2;
+            }
+            Err(err) => warn!(
+                "{} notifier gave up on {} after {} attempts : {}",
+                kind.as_str().unwrap_or(""),
+                notification.event_name(),
+                retries + 1,
+                err
+            ),
+        }
+    }
+}
+
+fn send(notifier: &NotifierConfig, notification: &Notification) -> Result<(), String> {
+    let vars = notification.vars();
+    let url = notifier.url.clone().unwrap_or_default();
+
+    match notifier.kind {
+        NotifierKind::Webhook => {
+            let body = match &notifier.body {
+                Some(body) => {
+                    // the values land inside the JSON strings of the template
+                    let escaped = vars
+                        .iter()
+                        .map(|(key, value)| {
+                            let json = serde_json::to_string(value).unwrap();
+                            (key.clone(), json[1..json.len() - 1].to_string())
+                        })
+                        .collect();
+                    render_template(body, &escaped)?
+                }
+                None => serde_json::to_string(&vars).unwrap(),
+            };
+
+            let mut args = vec!["-X".to_string(), "POST".to_string()];
+            let mut headers = notifier.headers.clone().unwrap_or_default();
+            headers
+                .entry("Content-Type".to_string())
+                .or_insert("application/json".to_string());
+            for (name, value) in headers {
+                args.push("-H".to_string());
+                args.push(format!("{}: {}", name, value));
+            }
+            args.extend(["--data-binary".to_string(), "@-".to_string(), url]);
+
+            return curl(&args, &body, None);
+        }
+        NotifierKind::Slack => {
+            let text = match &notifier.body {
+                Some(body) => render_template(body, &vars)?,
+                None => notification.message.clone(),
+            };
+            let body = serde_json::json!({ "text": text }).to_string();
+            let args = [
+                "-X",
+                "POST",
+                "-H",
+                "Content-Type: application/json",
+                "--data-binary",
+                "@-",
+                &url,
+            ]
+            .map(|arg| arg.to_string());
+
+            return curl(&args, &body, None);
+        }
+        NotifierKind::Email => {
+            let from = notifier.from.clone().unwrap_or_default();
+            let to = notifier.to.clone().unwrap_or_default();
+            let text = match &notifier.body {
+                Some(body) => render_template(body, &vars)?,
+                None => format!(
+                    "{}\n\nconfig: {}\nrun: {}\ncommit: {}\nbranch: {}\nhost: {}\n",
+                    &notification.message,
+                    &vars["config"],
+                    &vars["run_id"],
+                    &vars["commit"],
+                    &vars["branch"],
+                    &vars["hostname"]
+                ),
+            };
+
+            let mail = format!(
+                "From: {}\r\nTo: {}\r\nSubject: [fast_flow] {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}",
+                &from,
+                to.join(", "),
+                &notification.message.lines().next().unwrap_or(""),
+                chrono::Local::now().to_rfc2822(),
+                text.replace('\n', "\r\n")
+            );
+
+            let mut args = vec![
+                "--url".to_string(),
+                notifier.smtp.clone().unwrap_or_default(),
+                "--mail-from".to_string(),
+                from,
+                "--upload-file".to_string(),
+                "-".to_string(),
+            ];
+            for rcpt in to {
+                args.push("--mail-rcpt".to_string());
+                args.push(rcpt);
+            }
+
+            let user = notifier.username.as_ref().map(|username| {
+                format!(
+                    "{}:{}",
+                    username,
+                    notifier.password.clone().unwrap_or_default()
+                )
+            });
+
+            return curl(&args, &mail, user.as_deref());
+        }
+        NotifierKind::Command => {
+            let command = notifier.command.clone().unwrap_or_default();
+            let env: HashMap<String, String> = [
+                ("FLOW_EVENT", "event"),
+                ("FLOW_CONFIG", "config"),
+                ("FLOW_RUN_ID", "run_id"),
+                ("FLOW_COMMIT_SHA", "commit"),
+                ("FLOW_BRANCH", "branch"),
+                ("FLOW_FAILED_STAGE", "stage"),
+                ("FLOW_ERROR", "error"),
+                ("FLOW_MESSAGE", "message"),
+            ]
+            .into_iter()
+            .map(|(name, key)| (name.to_string(), vars[key].clone()))
+            .collect();
+
+            return run_command(&command, env, Duration::from_secs(REQUEST_TIMEOUT));
+        }
+    }
+}
+
+/// Runs a command notifier, killing its process group once `timeout` is over
+fn run_command(
+    command: &str,
+    env: HashMap<String, String>,
+    timeout: Duration,
+) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|err| err.to_string())?;
+
+    // drained on the side so a chatty command never blocks on a full pipe
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        })
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(err) => return Err(err.to_string()),
+        }
+
+        if started.elapsed() >= timeout {
+            let _ = kill_process_group(child.id());
+            let _ = child.wait();
+            return Err(format!(
+                "`{}` timed out after {}s, process group {} killed",
+                command,
+                timeout.as_secs(),
+                child.id()
+            ));
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    let stderr = stderr
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default();
+
+    match status.success() {
+        true => return Ok(()),
+        false => return Err(format!("`{}` {} : {}", command, status, stderr.trim())),
+    }
+}
+
+/// Runs curl with `input` on its stdin. Credentials go through a private curl config file
+/// so they never show up in the process list
+fn curl(args: &[String], input: &str, user: Option<&str>) -> Result<(), String> {
+    let mut command = Command::new("curl");
+    command
+        .args(["-sS", "--fail", "--max-time", &REQUEST_TIMEOUT.to_string()])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+
+    let config_path = std::env::temp_dir().join(format!(
+        "fast_flow-notify-{}-{:?}.curlrc",
+        std::process::id(),
+        thread::current().id()
+    ));
+    if let Some(user) = user {
+        let line = format!(
+            "user = \"{}\"\n",
+            user.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&config_path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|err| err.to_string())?;
+        command.arg("--config").arg(&config_path);
+    }
+
+    let res = command
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input.as_bytes())?;
+            }
+            child.wait_with_output()
+        })
+        .map_err(|err| format!("curl : {}", err));
+
+    if user.is_some() {
+        let _ = fs::remove_file(&config_path);
+    }
+
+    let output = res?;
+    match output.status.success() {
+        true => return Ok(()),
+        false => return Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn notification() -> Notification {
+        return Notification {
+            event: NotifyEvent::DeployFailed,
+            config: "app".to_string(),
+            run_id: Some("20240501-120000".to_string()),
+            commit: Some("0123456789abcdef".to_string()),
+            branch: Some("main".to_string()),
+            stage: Some("build".to_string()),
+            error: Some("exit status: 1".to_string()),
+            message: "deploy of [app] at 0123456 failed in build : \"make\" exited".to_string(),
+        };
+    }
+
+    fn notifier(config: Value) -> NotifierConfig {
+        return serde_json::from_value(config).unwrap();
+    }
+
+    /// HTTP server answering `statuses` in order, one connection each, and handing back
+    /// the head and body of the requests it got
+    fn http_stand_in(statuses: Vec<u16>) -> (String, JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        match name.eq_ignore_ascii_case("content-length") {
+                            true => value.trim().parse::<usize>().ok(),
+                            false => None,
+                        }
+                    })
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut stream = stream;
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {} stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                            status
+                        )
+                        .as_bytes(),
+                    )
+                    .unwrap();
+
+                requests.push((head, String::from_utf8(body).unwrap()));
+            }
+
+            return requests;
+        });
+
+        return (url, handle);
+    }
+
+    /// SMTP server accepting one mail, handing back the commands and the message it got
+    fn smtp_stand_in() -> (String, JoinHandle<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("smtp://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let mut commands = Vec::new();
+            let mut message = String::new();
+
+            stream.write_all(b"220 stand-in ready\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end().to_string();
+                let verb = line.split(' ').next().unwrap_or("").to_uppercase();
+                commands.push(line);
+
+                match verb.as_str() {
+                    "DATA" => {
+                        stream.write_all(b"354 go ahead\r\n").unwrap();
+                        loop {
+                            let mut data = String::new();
+                            reader.read_line(&mut data).unwrap();
+                            if data == ".\r\n" {
+                                break;
+                            }
+                            message.push_str(&data);
+                        }
+                        stream.write_all(b"250 queued\r\n").unwrap();
+                    }
+                    "QUIT" => {
+                        stream.write_all(b"221 bye\r\n").unwrap();
+                        break;
+                    }
+                    _ => stream.write_all(b"250 ok\r\n").unwrap(),
+                }
+            }
+
+            return (commands, message);
+        });
+
+        return (url, handle);
+    }
+
+    #[test]
+    fn webhook_posts_the_rendered_body_and_headers() {
+        let (url, server) = http_stand_in(vec![200]);
+        let webhook = notifier(json!({
+            "type": "webhook",
+            "url": url,
+            "body": "{\"text\": \"{{ message }}\", \"commit\": \"{{ commit }}\", \"stage\": \"{{ stage }}\"}",
+            "headers": { "Authorization": "Bearer s3cret" }
+        }));
+
+        send(&webhook, &notification()).unwrap();
+
+        let requests = server.join().unwrap();
+        let (head, body) = &requests[0];
+        assert!(head.starts_with("POST /hook HTTP/1.1"));
+        assert!(head.contains("Authorization: Bearer s3cret\r\n"));
+        assert!(head.contains("Content-Type: application/json\r\n"));
+
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["text"], notification().message);
+        assert_eq!(body["commit"], "0123456789abcdef");
+        assert_eq!(body["stage"], "build");
+    }
+
+    #[test]
+    fn slack_posts_the_message_as_text() {
+        let (url, server) = http_stand_in(vec![200]);
+        let slack = notifier(json!({ "type": "slack", "url": url }));
+
+        send(&slack, &notification()).unwrap();
+
+        let requests = server.join().unwrap();
+        let (head, body) = &requests[0];
+        assert!(head.contains("Content-Type: application/json\r\n"));
+        assert_eq!(
+            serde_json::from_str::<Value>(body).unwrap(),
+            json!({ "text": notification().message })
+        );
+    }
+
+    #[test]
+    fn email_sends_the_envelope_and_message() {
+        let (url, server) = smtp_stand_in();
+        let email = notifier(json!({
+            "type": "email",
+            "smtp": url,
+            "from": "flow@example.com",
+            "to": ["ops@example.com", "dev@example.com"]
+        }));
+
+        send(&email, &notification()).unwrap();
+
+        let (commands, message) = server.join().unwrap();
+        assert!(commands.contains(&"MAIL FROM:<flow@example.com>".to_string()));
+        assert!(commands.contains(&"RCPT TO:<ops@example.com>".to_string()));
+        assert!(commands.contains(&"RCPT TO:<dev@example.com>".to_string()));
+        assert!(message.contains("From: flow@example.com\r\n"));
+        assert!(message.contains("To: ops@example.com, dev@example.com\r\n"));
+        assert!(message.contains(&format!(
+            "Subject: [fast_flow] {}\r\n",
+            notification().message
+        )));
+        assert!(message.contains("commit: 0123456789abcdef\r\n"));
+    }
+
+    #[test]
+    fn retries_with_backoff_until_accepted() {
+        let (url, server) = http_stand_in(vec![500, 500, 200]);
+        let webhook = notifier(json!({
+            "type": "webhook",
+            "url": url,
+            "retries": 2,
+            "retry_delay": 1
+        }));
+
+        let started = Instant::now();
+        send_with_retries(&webhook, &notification());
+
+        // 1s before the first retry, doubled to 2s before the second
+        assert_eq!(server.join().unwrap().len(), 3);
+        assert!(started.elapsed() >= Duration::from_secs(3));
+    }
+
+    #[test]
+    fn command_gets_the_event_in_its_environment() {
+        let res = run_command(
+            "test \"$FLOW_EVENT\" = deploy_failed",
+            HashMap::from([("FLOW_EVENT".to_string(), "deploy_failed".to_string())]),
+            Duration::from_secs(5),
+        );
+        assert_eq!(res, Ok(()));
+
+        let err = run_command(
+            "echo nope >&2; exit 3",
+            HashMap::new(),
+            Duration::from_secs(5),
+        )
+        .unwrap_err();
+        assert!(err.contains("exit status: 3"));
+        assert!(err.ends_with(": nope"));
+    }
+
+    #[test]
+    fn command_is_killed_after_the_timeout() {
+        let started = Instant::now();
+        let err = run_command("sleep 30", HashMap::new(), Duration::from_secs(1)).unwrap_err();
+
+        assert!(err.contains("timed out after 1s"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
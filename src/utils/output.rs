@@ -0,0 +1,115 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use super::structs::OutputFormat;
+
+/// JSON scalars are valid YAML, strings become double quoted YAML scalars
+fn yaml_scalar(value: &Value) -> String {
+    return value.to_string();
+}
+
+fn write_yaml(value: &Value, indent: usize, out: &mut String) -> () {
+    let pad = " ".repeat(indent);
+
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            for item in items {
+                match item {
+                    Value::Object(map) if !map.is_empty() => {
+                        // the first key sits on the dash line, the others under it
+                        let mut nested = String::new();
+                        write_yaml(item, indent + 2, &mut nested);
+                        out.push_str(&format!("{}- {}", pad, &nested[indent + 2..]));
+                    }
+                    Value::Array(inner) if !inner.is_empty() => {
+                        out.push_str(&format!("{}-\n", pad));
+                        write_yaml(item, indent + 2, out);
+                    }
+                    _ => out.push_str(&format!("{}- {}\n", pad, yaml_scalar(item))),
+                }
+            }
+        }
+        Value::Object(map) if !map.is_empty() => {
+            for (key, item) in map {
+                match item {
+                    Value::Object(inner) if !inner.is_empty() => {
+                        out.push_str(&format!("{}{}:\n", pad, key));
+                        write_yaml(item, indent + 2, out);
+                    }
+                    Value::Array(inner) if !inner.is_empty() => {
+                        out.push_str(&format!("{}{}:\n", pad, key));
+                        write_yaml(item, indent + 2, out);
+                    }
+                    _ => out.push_str(&format!("{}{}: {}\n", pad, key, yaml_scalar(item))),
+                }
+            }
+        }
+        Value::Array(_) => out.push_str(&format!("{}[]\n", pad)),
+        Value::Object(_) => out.push_str(&format!("{}{{}}\n", pad)),
+        _ => out.push_str(&format!("{}{}\n", pad, yaml_scalar(value))),
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    let field = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) => s.clone(),
+                _ => item.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join("; "),
+        _ => value.to_string(),
+    };
+
+    match field.contains([',', '"', '\n', '\r']) {
+        true => return format!("\"{}\"", field.replace('"', "\"\"")),
+        false => return field,
+    }
+}
+
+/// `empty` is a blank record giving the headers even when there are no rows
+fn to_csv(rows: &[Value], empty: &Value) -> String {
+    let headers: Vec<String> = match empty {
+        Value::Object(map) => map.keys().cloned().collect(),
+        _ => return String::new(),
+    };
+
+    let mut lines = vec![headers.join(",")];
+    for row in rows {
+        lines.push(
+            headers
+                .iter()
+                .map(|header| csv_field(&row[header]))
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+    }
+
+    return lines.join("\n");
+}
+
+/// Renders `records` for scripts, the table format is left to the caller since every
+/// listing styles its own table
+pub fn format_records<T: Serialize + Default>(records: &[T], format: OutputFormat) -> String {
+    let rows: Vec<Value> = records
+        .iter()
+        .map(|record| serde_json::to_value(record).unwrap_or(Value::Null))
+        .collect();
+
+    match format {
+        OutputFormat::Json => return serde_json::to_string_pretty(&rows).unwrap_or_default(),
+        OutputFormat::Yaml => {
+            let mut out = String::new();
+            write_yaml(&Value::Array(rows), 0, &mut out);
+            return out.trim_end().to_string();
+        }
+        OutputFormat::Csv | OutputFormat::Table => {
+            let empty = serde_json::to_value(T::default()).unwrap_or(Value::Null);
+            return to_csv(&rows, &empty);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::{env, fs, os::unix::fs::MetadataExt, path::Path};
+
+use crate::core::utils::filesystem::load_file_parsed;
+
+use super::structs::GlobalConfig;
+
+const APP_NAME: &str = "fast_flow";
+
+/// Directories fast_flow keeps its configs and runtime data in
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub config_dir: String,
+    pub work_dir: String,
+    pub cache_dir: String,
+    pub process_dir: String,
+    pub logs_dir: String,
+    pub state_dir: String,
+}
+
+impl Paths {
+    /// Every directory under a single root, the layout of `--root` and `FAST_FLOW_HOME`
+    fn under(root: &str) -> Paths {
+        let root = root.trim_end_matches('/');
+        return Paths {
+            config_dir: format!("{}/config", root),
+            work_dir: format!("{}/tmp", root),
+            cache_dir: format!("{}/cache", root),
+            process_dir: format!("{}/process", root),
+            logs_dir: format!("{}/logs", root),
+            state_dir: format!("{}/state", root),
+        };
+    }
+
+    /// FHS locations for root
+    fn system() -> Paths {
+        // installs from before the directories were configurable keep their layout so
+        // running watchers and their pid files are still found
+        if Path::new(&format!("/etc/{}/process", APP_NAME)).is_dir() {
+            return Paths::under(&format!("/etc/{}", APP_NAME));
+        }
+
+        return Paths {
+            config_dir: format!("/etc/{}/config", APP_NAME),
+            work_dir: format!("/var/lib/{}/tmp", APP_NAME),
+            cache_dir: format!("/var/lib/{}/cache", APP_NAME),
+            process_dir: format!("/run/{}", APP_NAME),
+            logs_dir: format!("/var/log/{}", APP_NAME),
+            state_dir: format!("/var/lib/{}/state", APP_NAME),
+        };
+    }
+
+    /// XDG locations for the other users
+    fn user() -> Paths {
+        let home = env::var("HOME").unwrap_or("/tmp".to_string());
+        let xdg = |var: &str, fallback: &str| match env::var(var) {
+            Ok(dir) if dir.starts_with('/') => format!("{}/{}", dir, APP_NAME),
+            _ => format!("{}/{}/{}", &home, fallback, APP_NAME),
+        };
+
+        let state = xdg("XDG_STATE_HOME", ".local/state");
+
+        return Paths {
+            config_dir: format!("{}/config", xdg("XDG_CONFIG_HOME", ".config")),
+            work_dir: format!("{}/tmp", &state),
+            cache_dir: xdg("XDG_CACHE_HOME", ".cache"),
+            process_dir: match env::var("XDG_RUNTIME_DIR") {
+                Ok(dir) if dir.starts_with('/') => format!("{}/{}", dir, APP_NAME),
+                _ => format!("{}/process", &state),
+            },
+            logs_dir: format!("{}/logs", &state),
+            state_dir: format!("{}/state", &state),
+        };
+    }
+}
+
+fn is_root() -> bool {
+    // /proc/self belongs to the effective user of the process
+    return fs::metadata("/proc/self").is_ok_and(|meta| meta.uid() == 0);
+}
+
+/// Where the global config file is looked for
+pub fn global_config_path() -> String {
+    if is_root() {
+        return format!("/etc/{}/{}.json", APP_NAME, APP_NAME);
+    }
+
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if dir.starts_with('/') => dir,
+        _ => format!("{}/.config", env::var("HOME").unwrap_or("/tmp".to_string())),
+    };
+    return format!("{}/{}/{}.json", config_home, APP_NAME, APP_NAME);
+}
+
+/// Global settings, the defaults when there is no global config file or it can't be read
+pub fn load_global_config() -> GlobalConfig {
+    return load_file_parsed::<GlobalConfig>(&global_config_path()).unwrap_or_default();
+}
+
+/// Resolves the directories from `--root`, then `FAST_FLOW_HOME`, then the global config
+/// file, and falls back on the FHS layout for root and the XDG one for the other users
+pub fn resolve_paths(root: Option<String>) -> Paths {
+    if let Some(root) = root {
+        return Paths::under(&root);
+    }
+    if let Ok(root) = env::var("FAST_FLOW_HOME")
+        && !root.is_empty()
+    {
+        return Paths::under(&root);
+    }
+
+    let defaults = match is_root() {
+        true => Paths::system(),
+        false => Paths::user(),
+    };
+
+    let global_path = global_config_path();
+    let global = match load_file_parsed::<GlobalConfig>(&global_path) {
+        Ok(global) => global,
+        Err(err) => {
+            if Path::new(&global_path).exists() {
+                eprintln!("ignoring {} : {}", &global_path, err);
+            }
+            return defaults;
+        }
+    };
+
+    let base = match &global.root {
+        Some(root) => Paths::under(root),
+        None => defaults,
+    };
+
+    return Paths {
+        config_dir: global.config_dir.unwrap_or(base.config_dir),
+        work_dir: global.work_dir.unwrap_or(base.work_dir),
+        cache_dir: global.cache_dir.unwrap_or(base.cache_dir),
+        process_dir: global.process_dir.unwrap_or(base.process_dir),
+        logs_dir: global.logs_dir.unwrap_or(base.logs_dir),
+        state_dir: global.state_dir.unwrap_or(base.state_dir),
+    };
+}
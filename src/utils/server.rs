@@ -0,0 +1,547 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Cursor, Read, Write},
+    net::TcpListener,
+    process::exit,
+    thread,
+};
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use super::{
+    audit::audit_api,
+    daemon::serve_in_background,
+    logs::stream_logs,
+    paths::load_global_config,
+    state::load_state,
+    structs::{ActionRequest, ApiRole, ApiToken, HttpConfig, LogArgs, LogLine, OutputFormat},
+    subcommands::{
+        config_stats, has_entry_point, spawn_flow, stop_all_track, stop_processes, watch_stats,
+        watcher_pid,
+    },
+};
+
+pub const HTTP_PID: &str = "fast_flow.http.pid";
+const HTTP_LOG: &str = "fast_flow.http.log";
+
+/// Largest request body read by the POST actions
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// Lines the logs endpoint gives when no `tail` is asked for
+const DEFAULT_TAIL: usize = 200;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Directories the API reads, and the `--root` its actions run flow with
+#[derive(Clone)]
+struct ApiContext {
+    root: Option<String>,
+    state_dir: String,
+    process_dir: String,
+    logs_dir: String,
+    config_dir_path: String,
+}
+
+type ApiResponse = Response<Cursor<Vec<u8>>>;
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> ApiResponse {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    return Response::from_string(serde_json::to_string(body).unwrap())
+        .with_status_code(status)
+        .with_header(content_type);
+}
+
+fn error_response(status: u16, message: &str) -> ApiResponse {
+    return json_response(status, &json!({ "error": message }));
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let byte = bytes
+                    .get(idx + 1..idx + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match byte {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        idx += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        idx += 1;
+    }
+
+    return String::from_utf8_lossy(&decoded).to_string();
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    return query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+}
+
+/// Compares in full so the time taken doesn't tell how much of a token matched
+fn same_token(given: &str, token: &str) -> bool {
+    return given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+}
+
+/// Token of the request, from its `Authorization: Bearer` header or, for the log stream
+/// which browsers open without headers, its `token` parameter. Read from the global config
+/// on every request so a revoked token stops working right away
+fn authorize(request: &Request, query_token: Option<&String>) -> Option<ApiToken> {
+    let given = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| {
+            header
+                .value
+                .as_str()
+                .strip_prefix("Bearer ")
+                .map(|token| token.trim().to_string())
+        })
+        .or_else(|| query_token.cloned())?;
+
+    return load_global_config()
+        .http?
+        .tokens
+        .into_iter()
+        .find(|token| same_token(&given, &token.token));
+}
+
+/// Refs and commits handed to flow as arguments, they must not pass for an option
+fn check_ref(git_ref: &str) -> Result<(), String> {
+    let valid = !git_ref.is_empty()
+        && !git_ref.starts_with('-')
+        && !git_ref.chars().any(|c| c.is_whitespace() || c.is_control());
+
+    match valid {
+        true => return Ok(()),
+        false => return Err(format!("`{}` is not a valid ref", git_ref)),
+    }
+}
+
+impl ApiContext {
+    fn config_exists(&self, name: &str) -> bool {
+        return !name.is_empty()
+            && !name.contains('/')
+            && !name.starts_with('.')
+            && fs::metadata(format!("{}/{}.config.json", &self.config_dir_path, name)).is_ok();
+    }
+
+    fn log_args(&self, name: &str, query: &HashMap<String, String>) -> Result<LogArgs, String> {
+        let level = match query.get("level") {
+            Some(level) => Some(
+                level
+                    .parse()
+                    .map_err(|_| format!("`{}` is not a log level", level))?,
+            ),
+            None => None,
+        };
+        let tail = match query.get("tail") {
+            Some(tail) => tail
+                .parse()
+                .map_err(|_| format!("`{}` is not a number of lines", tail))?,
+            None => DEFAULT_TAIL,
+        };
+
+        return Ok(LogArgs {
+            name: name.to_string(),
+            process: query
+                .get("stream")
+                .is_some_and(|stream| stream == "process"),
+            watch: false,
+            follow: false,
+            tail: Some(tail),
+            since: query.get("since").cloned(),
+            level,
+            run: query.get("run").cloned(),
+            output: OutputFormat::Json,
+        });
+    }
+
+    fn read_logs(&self, args: &LogArgs) -> Result<Vec<LogLine>, String> {
+        let mut records = Vec::<LogLine>::new();
+        stream_logs(&self.logs_dir, args, &mut |lines| {
+            records.extend(lines.iter().map(|(_, parsed)| parsed.clone()));
+            true
+        })?;
+        return Ok(records);
+    }
+
+    /// Runs `action` on `name` the way `flow top` does for the token named `token`, telling
+    /// what was done
+    fn run_action(
+        &self,
+        name: &str,
+        action: &str,
+        body: ActionRequest,
+        token: &str,
+    ) -> Result<String, String> {
+        let watching = watcher_pid(&self.process_dir, name).is_some();
+        let watch_log = format!("{}/{}.watch.log", &self.logs_dir, name);
+
+        // a running watcher picks deploys up, otherwise they run right away
+        let spawn_deploy = |mut args: Vec<String>| -> Result<(), String> {
+            if watching {
+                args.push("--daemon".to_string());
+            }
+            let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+            match watching {
+                true => return spawn_flow(&self.root, &args, None, Some(token)),
+                false => return spawn_flow(&self.root, &args, Some(&watch_log), Some(token)),
+            }
+        };
+
+        match action {
+            "deploy" => {
+                let mut args = vec!["deploy".to_string(), "-n".to_string(), name.to_string()];
+                if let Some(git_ref) = &body.git_ref {
+                    check_ref(git_ref)?;
+                    args.push(format!("--ref={}", git_ref));
+                }
+                if body.force {
+                    args.push("--force".to_string());
+                }
+                spawn_deploy(args)?;
+                return Ok(format!("deploying [{}]", name));
+            }
+            "rollback" => {
+                let mut args = vec!["rollback".to_string(), "-n".to_string(), name.to_string()];
+                if let Some(to) = &body.to {
+                    check_ref(to)?;
+                    args.push(format!("--to={}", to));
+                }
+                spawn_deploy(args)?;
+                return Ok(format!("rolling [{}] back", name));
+            }
+            "restart" => {
+                if !has_entry_point(&self.config_dir_path, name) {
+                    return Err(format!(
+                        "[{}] has no entry point yet, set it up once with flow start -n {}",
+                        name, name
+                    ));
+                }
+                stop_processes(&self.process_dir, name);
+                spawn_flow(&self.root, &["start", "-n", name], None, Some(token))?;
+                return Ok(format!("restarting [{}]", name));
+            }
+            "stop" => match body.target.as_deref() {
+                Some("app") => {
+                    let stopped = stop_processes(&self.process_dir, name)
+                        .into_iter()
+                        .filter(|(_, stopped)| *stopped)
+                        .count();
+                    return Ok(format!("stopped {} process(es) of [{}]", stopped, name));
+                }
+                None | Some("watcher") => {
+                    stop_all_track(&self.process_dir, Some(name.to_string()), true);
+                    return Ok(format!("stopped watching [{}]", name));
+                }
+                Some(target) => {
+                    return Err(format!("`{}` can't be stopped, use watcher or app", target));
+                }
+            },
+            _ => return Err(format!("unknown action `{}`", action)),
+        }
+    }
+
+    /// Sends the lines of a log as server-sent events, as they are written
+    fn stream(&self, request: Request, args: LogArgs) -> () {
+        let mut writer = request.into_writer();
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+        if writer
+            .write_all(head.as_bytes())
+            .and_then(|_| writer.flush())
+            .is_err()
+        {
+            return;
+        }
+
+        // stops at the first write after the client left, a quiet log sends a comment
+        // now and then to find out
+        let res = stream_logs(&self.logs_dir, &args, &mut |lines| {
+            if lines.is_empty() && writer.write_all(b":\n\n").is_err() {
+                return false;
+            }
+            for (_, parsed) in lines {
+                let event = format!("data: {}\n\n", serde_json::to_string(parsed).unwrap());
+                if writer.write_all(event.as_bytes()).is_err() {
+                    return false;
+                }
+            }
+            writer.flush().is_ok()
+        });
+
+        if let Err(err) = res {
+            let event = format!("event: error\ndata: {}\n\n", json!(err));
+            let _ = writer.write_all(event.as_bytes());
+        }
+    }
+
+    fn handle(&self, mut request: Request) -> () {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let query = parse_query(query);
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let method = request.method().clone();
+
+        if method == Method::Get && segments == [""] {
+            let content_type =
+                Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+            let _ = request.respond(Response::from_string(DASHBOARD).with_header(content_type));
+            return;
+        }
+
+        // only the log stream takes its token as a parameter, it would leak into the
+        // access logs of proxies everywhere else
+        let is_stream = method == Method::Get
+            && matches!(segments.as_slice(), ["api", "configs", _, "logs", "stream"]);
+        let query_token = query.get("token").filter(|_| is_stream);
+
+        let token = match authorize(&request, query_token) {
+            Some(token) => token,
+            None => {
+                let _ = request.respond(error_response(401, "missing or unknown token"));
+                return;
+            }
+        };
+
+        // the path only, the token parameter of the log stream stays out of the log
+        println!(
+            "[{}] {} {} {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            &token.name,
+            &method,
+            path
+        );
+
+        let response = match (&method, segments.as_slice()) {
+            (Method::Get, ["api", "status"]) => {
+                match watch_stats(&self.state_dir, &self.process_dir, &self.config_dir_path) {
+                    Ok(stats) => json_response(200, &stats),
+                    Err(err) => error_response(500, &err),
+                }
+            }
+            (Method::Get, ["api", "configs"]) => {
+                match config_stats(&self.process_dir, &self.config_dir_path) {
+                    Ok(stats) => json_response(200, &stats),
+                    Err(err) => error_response(500, &err),
+                }
+            }
+            (_, ["api", "configs", name, ..]) if !self.config_exists(name) => {
+                error_response(404, &format!("no config named [{}]", name))
+            }
+            // configs carry notifier credentials and host settings, only admins read them
+            (Method::Get, ["api", "configs", _]) if token.role != ApiRole::Admin => error_response(
+                403,
+                &format!(
+                    "token [{}] can't read configs, they hold secrets",
+                    &token.name
+                ),
+            ),
+            (Method::Get, ["api", "configs", name]) => {
+                let config_file_path = format!("{}/{}.config.json", &self.config_dir_path, name);
+                match fs::read_to_string(&config_file_path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|content| {
+                        serde_json::from_str::<Value>(&content).map_err(|err| err.to_string())
+                    }) {
+                    Ok(config) => json_response(200, &config),
+                    Err(err) => error_response(500, &err),
+                }
+            }
+            (Method::Get, ["api", "configs", name, "history"]) => {
+                let mut history = load_state(&self.state_dir, name).history;
+                history.reverse();
+                json_response(200, &history)
+            }
+            (Method::Get, ["api", "configs", name, "runs", run]) => {
+                let deploy = load_state(&self.state_dir, name)
+                    .history
+                    .into_iter()
+                    .find(|deploy| &deploy.run_id == run);
+
+                let mut args = match self.log_args(name, &query) {
+                    Ok(args) => args,
+                    Err(err) => {
+                        let _ = request.respond(error_response(400, &err));
+                        return;
+                    }
+                };
+                args.process = false;
+                args.tail = None;
+                args.run = Some(run.to_string());
+
+                match self.read_logs(&args) {
+                    Ok(logs) if deploy.is_none() && logs.is_empty() => {
+                        error_response(404, &format!("no run {} for [{}]", run, name))
+                    }
+                    Ok(logs) => json_response(200, &json!({ "deploy": deploy, "logs": logs })),
+                    Err(err) => error_response(500, &err),
+                }
+            }
+            (Method::Get, ["api", "configs", name, "logs"]) => {
+                match self
+                    .log_args(name, &query)
+                    .and_then(|args| self.read_logs(&args))
+                {
+                    Ok(logs) => json_response(200, &logs),
+                    Err(err) => error_response(400, &err),
+                }
+            }
+            (Method::Get, ["api", "configs", name, "logs", "stream"]) => {
+                match self.log_args(name, &query) {
+                    Ok(mut args) => {
+                        args.follow = true;
+                        // the backlog is what the client asked for, 10 lines otherwise
+                        args.tail = query.get("tail").and(args.tail);
+                        return self.stream(request, args);
+                    }
+                    Err(err) => error_response(400, &err),
+                }
+            }
+            (Method::Post, ["api", "configs", name, action]) if token.role != ApiRole::Admin => {
+                let reason = format!("token [{}] is read-only", &token.name);
+                audit_api(
+                    &self.logs_dir,
+                    &token.name,
+                    action,
+                    name,
+                    vec![path.to_string()],
+                    "denied",
+                    Some(reason.clone()),
+                );
+                error_response(403, &reason)
+            }
+            (
+                Method::Post,
+                [
+                    "api",
+                    "configs",
+                    name,
+                    action @ ("deploy" | "rollback" | "restart" | "stop"),
+                ],
+            ) => {
+                let mut content = String::new();
+                let body = match request
+                    .as_reader()
+                    .take(MAX_BODY_BYTES)
+                    .read_to_string(&mut content)
+                {
+                    Ok(_) if content.trim().is_empty() => Ok(ActionRequest::default()),
+                    Ok(_) => serde_json::from_str::<ActionRequest>(&content)
+                        .map_err(|err| format!("invalid body : {}", err)),
+                    Err(err) => Err(err.to_string()),
+                };
+
+                let res = body.and_then(|body| self.run_action(name, action, body, &token.name));
+
+                // the flow commands started record how they end, this is the request
+                let mut args = vec![path.to_string()];
+                if !content.trim().is_empty() {
+                    args.push(content.trim().to_string());
+                }
+                let (outcome, reason) = match (&res, *action) {
+                    (Ok(_), "stop") => ("success", None),
+                    (Ok(_), _) => ("accepted", None),
+                    (Err(err), _) => ("failed", Some(err.clone())),
+                };
+                audit_api(
+                    &self.logs_dir,
+                    &token.name,
+                    action,
+                    name,
+                    args,
+                    outcome,
+                    reason,
+                );
+
+                match res {
+                    Ok(message) => json_response(202, &json!({ "message": message })),
+                    Err(err) => error_response(400, &err),
+                }
+            }
+            _ => error_response(404, "not found"),
+        };
+
+        if let Err(err) = request.respond(response) {
+            eprintln!("Failed to answer {} : {}", path, err);
+        }
+    }
+}
+
+fn serve_api(listener: TcpListener, ctx: ApiContext) -> () {
+    let server = match Server::from_listener(listener, None) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Failed to start the http api : {}", err);
+            return;
+        }
+    };
+
+    // a thread per request, log streams stay open as long as their client
+    for request in server.incoming_requests() {
+        let ctx = ctx.clone();
+        thread::spawn(move || ctx.handle(request));
+    }
+}
+
+/// Starts the daemon serving the HTTP API and the dashboard, unless one is already running
+pub fn start_http_daemon(
+    root: Option<String>,
+    http: &HttpConfig,
+    state_dir: &str,
+    process_dir: &str,
+    logs_dir: &str,
+    config_dir_path: &str,
+) -> () {
+    if http.tokens.is_empty() {
+        println!("http api not started, the http section has no tokens to accept");
+        return;
+    }
+
+    let pid_file_path = format!("{}/{}", process_dir, HTTP_PID);
+    let log_file_path = format!("{}/{}", logs_dir, HTTP_LOG);
+
+    if let Some(listener) = serve_in_background(
+        "http api",
+        &http.listen,
+        "/",
+        &pid_file_path,
+        &log_file_path,
+    ) {
+        let ctx = ApiContext {
+            root,
+            state_dir: state_dir.to_string(),
+            process_dir: process_dir.to_string(),
+            logs_dir: logs_dir.to_string(),
+            config_dir_path: config_dir_path.to_string(),
+        };
+        serve_api(listener, ctx);
+        exit(0);
+    }
+}
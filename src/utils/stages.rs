@@ -0,0 +1,272 @@
+use log::{Level, error, info};
+use std::{collections::HashMap, sync::mpsc, thread};
+
+use crate::core::utils::{
+    command::{execute_commande, execute_step, log_step},
+    remote::{remote_step, resolve_host},
+};
+
+use super::{
+    notify::{Notification, notify},
+    state::{record_deploy, record_stage},
+    structs::{BuildStep, HostOutcome, NotifierConfig, NotifyEvent, SshHost, StepConfig, StepTag},
+};
+
+/// Everything the stages of a single run share
+pub struct StageContext {
+    pub repo_dir: String,
+    pub env: HashMap<String, String>,
+    pub max_parallel: usize,
+    pub hosts: HashMap<String, SshHost>,
+    pub on_failure: Vec<BuildStep>,
+    /// Configuration name and where the outcome of the run is recorded
+    pub name: String,
+    pub state_dir: String,
+    pub started_at: chrono::DateTime<chrono::Local>,
+    /// Notifiers told about the start and outcome of the run
+    pub notify: Vec<NotifierConfig>,
+    /// Outcome of the deploy on each remote host, filled by the mouve stage
+    pub host_outcomes: Vec<HostOutcome>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StepState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// Resolves the dependency graph of a stage, giving for every step the indexes of the
+/// steps it needs. A stage where no step declares `needs` keeps running in order.
+pub fn resolve_needs(steps: &[StepConfig]) -> Result<Vec<Vec<usize>>, String> {
+    let graph = steps.iter().any(|step| step.needs.is_some());
+
+    if !graph {
+        return Ok((0..steps.len())
+            .map(|idx| match idx {
+                0 => vec![],
+                _ => vec![idx - 1],
+            })
+            .collect());
+    }
+
+    let mut names = HashMap::<String, usize>::new();
+    for (idx, step) in steps.iter().enumerate() {
+        if let Some(name) = &step.name
+            && names.insert(name.clone(), idx).is_some()
+        {
+            return Err(format!("step name `{}` is used more than once", name));
+        }
+    }
+
+    let mut deps = Vec::<Vec<usize>>::new();
+    for step in steps {
+        let mut step_deps = Vec::new();
+        for need in step.needs.clone().unwrap_or_default() {
+            match names.get(&need) {
+                Some(dep) => step_deps.push(*dep),
+                None => {
+                    return Err(format!(
+                        "step `{}` needs unknown step `{}`",
+                        step.name.clone().unwrap_or(step.run.clone()),
+                        need
+                    ));
+                }
+            }
+        }
+        deps.push(step_deps);
+    }
+
+    // Kahn's algorithm, anything left unvisited sits on a cycle
+    let mut remaining: Vec<usize> = deps.iter().map(|d| d.len()).collect();
+    let mut ready: Vec<usize> = (0..steps.len()).filter(|i| remaining[*i] == 0).collect();
+    let mut visited = 0;
+    while let Some(idx) = ready.pop() {
+        visited += 1;
+        for (other, other_deps) in deps.iter().enumerate() {
+            for dep in other_deps {
+                if *dep == idx {
+                    remaining[other] -= 1;
+                    if remaining[other] == 0 {
+                        ready.push(other);
+                    }
+                }
+            }
+        }
+    }
+    if visited != steps.len() {
+        return Err("the steps `needs` form a cycle".to_string());
+    }
+
+    return Ok(deps);
+}
+
+/// Runs the steps of a pipeline stage, starting every step whose `needs` are met
+/// with at most `max_parallel` steps at once. A failing step cancels its dependents
+/// unless it is marked `continue_on_error`.
+pub fn run_stage(stage: &str, steps: &[BuildStep], ctx: &StageContext) -> Result<(), String> {
+    if steps.is_empty() {
+        return Ok(());
+    }
+
+    info!(stage; "Starting {} stage", stage);
+    record_stage(ctx, stage);
+
+    let steps: Vec<StepConfig> = steps
+        .iter()
+        .map(|build_step| {
+            let mut step = build_step.to_step();
+            // the step's own variables win over the deploy metadata
+            let mut step_env = ctx.env.clone();
+            step_env.extend(step.env.unwrap_or_default());
+            step.env = Some(step_env);
+            step
+        })
+        .collect();
+
+    let deps = resolve_needs(&steps)?;
+
+    let tags: Vec<StepTag> = steps
+        .iter()
+        .enumerate()
+        .map(|(idx, step)| StepTag {
+            stage: stage.to_string(),
+            step: idx + 1,
+            name: step.name.clone(),
+            host: step.host.clone(),
+        })
+        .collect();
+
+    // remote steps become the local ssh command running them
+    let steps: Vec<StepConfig> = steps
+        .into_iter()
+        .map(|step| match &step.host {
+            Some(host) => remote_step(&step, &resolve_host(host, &ctx.hosts)),
+            None => step,
+        })
+        .collect();
+
+    let mut states = vec![StepState::Pending; steps.len()];
+    let mut running = 0;
+    let mut failures = Vec::<String>::new();
+    let (sender, receiver) = mpsc::channel::<(usize, Result<(), String>)>();
+
+    loop {
+        // start everything that is ready, within the parallel limit
+        for idx in 0..steps.len() {
+            if running >= ctx.max_parallel.max(1) {
+                break;
+            }
+            if states[idx] != StepState::Pending {
+                continue;
+            }
+            if !deps[idx].iter().all(|dep| states[*dep] == StepState::Done) {
+                continue;
+            }
+
+            states[idx] = StepState::Running;
+            running += 1;
+
+            let step = steps[idx].clone();
+            let tag = tags[idx].clone();
+            let repo_dir = ctx.repo_dir.clone();
+            let sender = sender.clone();
+
+            log_step(Level::Info, &tag, None, &format!("running {}", &step.run));
+
+            thread::spawn(move || {
+                let res = execute_step(&step, &repo_dir, &tag);
+                let _ = sender.send((idx, res));
+            });
+        }
+
+        if running == 0 {
+            break;
+        }
+
+        let (idx, res) = match receiver.recv() {
+            Ok(msg) => msg,
+            Err(err) => return Err(err.to_string()),
+        };
+        running -= 1;
+
+        let tag = &tags[idx];
+        let step = &steps[idx];
+
+        match res {
+            Ok(_) => {
+                let message = format!("{} : commande success ", &step.run);
+                log_step(Level::Info, tag, None, &message);
+                states[idx] = StepState::Done;
+            }
+            Err(err) if step.continue_on_error.unwrap_or(false) => {
+                log_step(Level::Warn, tag, None, &format!("{} : {}", &step.run, err));
+                log_step(Level::Warn, tag, None, "continuing despite the failure");
+                states[idx] = StepState::Done;
+            }
+            Err(err) => {
+                log_step(Level::Error, tag, None, &format!("{} : {}", &step.run, err));
+                failures.push(format!("[{}] {} : {}", tag.label(), &step.run, err));
+                states[idx] = StepState::Failed;
+                cancel_dependents(idx, &deps, &mut states, &tags);
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(failures.join("\n"));
+    }
+
+    return Ok(());
+}
+
+fn cancel_dependents(
+    failed: usize,
+    deps: &[Vec<usize>],
+    states: &mut [StepState],
+    tags: &[StepTag],
+) -> () {
+    for (idx, step_deps) in deps.iter().enumerate() {
+        if states[idx] == StepState::Pending && step_deps.contains(&failed) {
+            let message = format!("cancelled, [{}] failed", tags[failed].label());
+            log_step(Level::Warn, &tags[idx], None, &message);
+            states[idx] = StepState::Cancelled;
+            cancel_dependents(idx, deps, states, tags);
+        }
+    }
+}
+
+/// Logs a failed run, fires the `on_failure` hooks and removes the checkout
+pub fn fail_run(stage: &str, err: &str, ctx: &StageContext) -> () {
+    error!(stage; "{} stage failed : {}", stage, err);
+
+    let mut env = ctx.env.clone();
+    env.insert("FLOW_FAILED_STAGE".to_string(), stage.to_string());
+
+    let hook_ctx = StageContext {
+        repo_dir: ctx.repo_dir.clone(),
+        env,
+        max_parallel: ctx.max_parallel,
+        hosts: ctx.hosts.clone(),
+        on_failure: Vec::new(),
+        name: ctx.name.clone(),
+        state_dir: ctx.state_dir.clone(),
+        started_at: ctx.started_at,
+        notify: Vec::new(),
+        host_outcomes: ctx.host_outcomes.clone(),
+    };
+
+    if let Err(hook_err) = run_stage("on_failure", &ctx.on_failure, &hook_ctx) {
+        error!("{}", hook_err);
+    }
+
+    record_deploy(ctx, Some((stage, err)));
+    notify(
+        &ctx.notify,
+        Notification::for_run(NotifyEvent::DeployFailed, ctx, Some((stage, err))),
+    );
+
+    let _ = execute_commande(&format!("rm -rf {}", &ctx.repo_dir));
+}
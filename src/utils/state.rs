@@ -0,0 +1,130 @@
+use log::warn;
+use std::fs;
+
+use crate::core::utils::filesystem::{check_dir_exist_or_create, load_file_parsed};
+
+use super::{
+    metrics::BUILD_DURATION_BUCKETS,
+    stages::StageContext,
+    structs::{AppState, DeployRecord},
+    utils::DEFAULT_HISTORY_SIZE,
+};
+
+fn state_path(state_dir: &str, name: &str) -> String {
+    return format!("{}/{}.state.json", state_dir, name);
+}
+
+pub fn load_state(state_dir: &str, name: &str) -> AppState {
+    return load_file_parsed::<AppState>(&state_path(state_dir, name)).unwrap_or_default();
+}
+
+fn save_state(state_dir: &str, name: &str, state: &AppState) -> Result<(), String> {
+    let path = state_path(state_dir, name);
+    check_dir_exist_or_create(&path);
+
+    // swapped in whole so a reader never sees half a state file
+    let tmp_path = format!("{}.tmp", &path);
+    fs::write(&tmp_path, serde_json::to_string_pretty(state).unwrap())
+        .and_then(|_| fs::rename(&tmp_path, &path))
+        .map_err(|err| err.to_string())
+}
+
+/// Records the outcome of the run of `ctx`, `failed` holds the failing stage and error
+pub fn record_deploy(ctx: &StageContext, failed: Option<(&str, &str)>) -> () {
+    let finished = chrono::Local::now();
+    let meta = |key: &str| ctx.env.get(key).cloned().unwrap_or_default();
+
+    let mut state = load_state(&ctx.state_dir, &ctx.name);
+    state.stage = None;
+    let record = DeployRecord {
+        sha: meta("FLOW_COMMIT_SHA"),
+        branch: meta("FLOW_BRANCH"),
+        run_id: meta("FLOW_RUN_ID"),
+        started_at: ctx.started_at.to_rfc3339(),
+        finished_at: finished.to_rfc3339(),
+        duration_secs: (finished - ctx.started_at).num_milliseconds() as f64 / 1000.0,
+        result: match failed {
+            Some(_) => "failed".to_string(),
+            None => "success".to_string(),
+        },
+        stage: failed.map(|(stage, _)| stage.to_string()),
+        error: failed.map(|(_, err)| err.to_string()),
+        hosts: ctx.host_outcomes.clone(),
+    };
+
+    state.history.push(record.clone());
+    if state.history.len() > DEFAULT_HISTORY_SIZE {
+        let extra = state.history.len() - DEFAULT_HISTORY_SIZE;
+        state.history.drain(..extra);
+    }
+    state.last_deploy = Some(record);
+
+    match failed {
+        Some(_) => state.metrics.deploys_failed += 1,
+        None => {
+            state.metrics.deploys_succeeded += 1;
+            state.metrics.last_success_at = Some(finished.timestamp());
+        }
+    }
+
+    if let Err(err) = save_state(&ctx.state_dir, &ctx.name, &state) {
+        warn!("Failed to record the run : {}", err);
+    }
+}
+
+/// Records how long the build of the run of `ctx` took
+pub fn record_build(ctx: &StageContext, secs: f64) -> () {
+    let mut state = load_state(&ctx.state_dir, &ctx.name);
+    let metrics = &mut state.metrics;
+
+    // one bucket per bound plus +Inf, older state files may have none yet
+    metrics
+        .build_buckets
+        .resize(BUILD_DURATION_BUCKETS.len() + 1, 0);
+    let bucket = BUILD_DURATION_BUCKETS
+        .iter()
+        .position(|bound| secs <= *bound)
+        .unwrap_or(BUILD_DURATION_BUCKETS.len());
+    metrics.build_buckets[bucket] += 1;
+    metrics.build_seconds_sum += secs;
+    metrics.build_count += 1;
+
+    let _ = save_state(&ctx.state_dir, &ctx.name, &state);
+}
+
+/// Counts a poll of `name` that failed before its run could start
+pub fn record_poll_error(state_dir: &str, name: &str) -> () {
+    let mut state = load_state(state_dir, name);
+    state.metrics.poll_errors += 1;
+    let _ = save_state(state_dir, name, &state);
+}
+
+/// Records the pipeline stage the run of `ctx` entered
+pub fn record_stage(ctx: &StageContext, stage: &str) -> () {
+    let mut state = load_state(&ctx.state_dir, &ctx.name);
+    state.stage = Some(stage.to_string());
+    let _ = save_state(&ctx.state_dir, &ctx.name, &state);
+}
+
+/// Counts a restart of the entry points of `name`
+pub fn record_restart(state_dir: &str, name: &str) -> () {
+    let mut state = load_state(state_dir, name);
+    state.restarts += 1;
+    let _ = save_state(state_dir, name, &state);
+}
+
+/// Commit to roll `name` back to, the last successful deploy of another commit than the
+/// one deployed now
+pub fn rollback_target(state_dir: &str, name: &str) -> Option<String> {
+    let state = load_state(state_dir, name);
+    let mut successes = state
+        .history
+        .iter()
+        .rev()
+        .filter(|deploy| deploy.result == "success");
+
+    let current = successes.next()?.sha.clone();
+    return successes
+        .find(|deploy| deploy.sha != current)
+        .map(|deploy| deploy.sha.clone());
+}
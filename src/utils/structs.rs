@@ -1,7 +1,14 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tabled::Tabled;
 
+use super::table::{
+    display_age, display_args, display_cpu, display_hosts, display_memory, display_option,
+    display_problems, display_secs, display_short_sha, display_time, display_version,
+    display_watcher,
+};
+
 #[derive(Parser)]
 #[command(
     name = "flow",
@@ -10,12 +17,22 @@ use tabled::Tabled;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Root directory of the configs and runtime data, overrides FAST_FLOW_HOME"
+    )]
+    pub root: Option<String>,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Create a new configuration file with boilerplate structure
-    Config(ConfigArgs),
+    /// Create a configuration step by step, checking the repository on the way
+    Init(InitArgs),
+
+    /// Create a new configuration file with boilerplate structure, or manage the existing ones
+    Config(ConfigCommandArgs),
 
     /// Start tracking all configured repositories for changes
     Watch(OptConfigArgs),
@@ -24,13 +41,327 @@ pub enum Commands {
     Stop(OptConfigArgs),
 
     /// Display current status of watched repositories in table format
-    Status,
+    Status(StatusArgs),
+
+    /// Full screen status refreshed in place, with logs and keybindings to act on an app
+    Top,
 
     /// Display the logs of the selected tracked repository
-    Log(ConfigArgs),
+    Log(LogArgs),
 
     /// Start the execution of the selected application in a new process
     Start(OptConfigArgs),
+
+    /// Inspect or clean the build artifact cache
+    Cache(CacheArgs),
+
+    /// Run the pipeline of a configuration once in the foreground
+    Run(RunArgs),
+
+    /// Deploy a configuration now instead of waiting for the next poll
+    Deploy(DeployArgs),
+
+    /// Deploy again the commit of the last successful deploy before the current one
+    Rollback(RollbackArgs),
+
+    /// List the last deploys of a configuration, newest first
+    History(HistoryArgs),
+
+    /// Show who changed, stopped, started or deployed what, from the audit log
+    Audit(AuditArgs),
+}
+
+#[derive(Args)]
+pub struct DeployArgs {
+    #[arg(short, long, help = "Name of the configuration to deploy")]
+    pub name: String,
+
+    #[arg(
+        short,
+        long = "ref",
+        help = "Optional: Commit sha, branch or tag to deploy instead of the branch head"
+    )]
+    pub git_ref: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Redeploy even when the commit is already deployed"
+    )]
+    pub force: bool,
+
+    #[arg(short, long, help = "Hand the deploy to the running watcher")]
+    pub daemon: bool,
+}
+
+#[derive(Args)]
+pub struct AuditArgs {
+    #[arg(short, long, help = "Only show the actions on this configuration")]
+    pub name: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Only show the actions of this Unix user or API token"
+    )]
+    pub user: Option<String>,
+
+    #[arg(short, long, help = "Only show this action, such as deploy or stop")]
+    pub action: Option<String>,
+
+    #[arg(
+        long,
+        help = "Only show actions newer than a duration such as 30m, 1h or 2d, or than a date"
+    )]
+    pub since: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        value_name = "ENTRIES",
+        help = "Only show the last ENTRIES entries"
+    )]
+    pub tail: Option<usize>,
+
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    pub output: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct RollbackArgs {
+    #[arg(short, long, help = "Name of the configuration to roll back")]
+    pub name: String,
+
+    #[arg(
+        short,
+        long,
+        help = "Optional: Commit to roll back to instead of the previous successful deploy"
+    )]
+    pub to: Option<String>,
+
+    #[arg(short, long, help = "Hand the rollback to the running watcher")]
+    pub daemon: bool,
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    #[arg(short, long, help = "Name of the configuration to list the deploys of")]
+    pub name: String,
+
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    pub output: OutputFormat,
+}
+
+/// Global settings, read from `/etc/fast_flow/fast_flow.json` for root and
+/// `~/.config/fast_flow/fast_flow.json` for the other users
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct GlobalConfig {
+    /// Puts every directory under this root, like `--root`
+    pub root: Option<String>,
+    pub config_dir: Option<String>,
+    pub work_dir: Option<String>,
+    pub cache_dir: Option<String>,
+    pub process_dir: Option<String>,
+    pub logs_dir: Option<String>,
+    pub state_dir: Option<String>,
+    /// Address of the Prometheus `/metrics` endpoint `flow watch` starts, such as
+    /// `127.0.0.1:9464`. Left out, no endpoint is started
+    pub metrics_listen: Option<String>,
+    /// Notifiers fired for every configuration, on top of their own
+    pub notify: Option<Vec<NotifierConfig>>,
+    /// HTTP API and dashboard `flow watch` starts. Left out, no server is started
+    pub http: Option<HttpConfig>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct HttpConfig {
+    /// Address to serve on, such as `127.0.0.1:8787`
+    pub listen: String,
+    /// Tokens accepted by the API, it answers nothing without one
+    pub tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiToken {
+    /// Who the token was given to
+    pub name: String,
+    pub token: String,
+    pub role: ApiRole,
+}
+
+/// `read` tokens can only look, `admin` ones can also read the configs and deploy, roll back,
+/// restart and stop
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiRole {
+    Read,
+    Admin,
+}
+
+/// Body of the POST actions of the HTTP API, each action reads the fields it needs
+#[derive(Debug, Deserialize, Default)]
+pub struct ActionRequest {
+    /// Commit sha, branch or tag a deploy should use instead of the branch head
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    /// Redeploy even when the commit is already deployed
+    #[serde(default)]
+    pub force: bool,
+    /// Commit a rollback should go back to instead of the previous successful deploy
+    pub to: Option<String>,
+    /// What a stop stops, `watcher` (the default) or `app`
+    pub target: Option<String>,
+}
+
+/// Deploy asked with `flow deploy`, left in the work dir for the watcher to pick up
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct DeployRequest {
+    pub git_ref: Option<String>,
+    pub force: bool,
+    /// Set for the watcher's own polls, the only deploys a hold applies to
+    #[serde(skip)]
+    pub poll: bool,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    #[arg(short, long, help = "Name of the configuration to run")]
+    pub name: String,
+
+    #[arg(long, help = "Show what the run would do without changing anything")]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        requires = "dry_run",
+        help = "With --dry-run, also run pre_build and build in the throwaway checkout"
+    )]
+    pub run_build: bool,
+}
+
+#[derive(Args)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommands,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// List the cached build artifacts
+    Ls(CacheLsArgs),
+
+    /// Remove the cached build artifacts past the eviction policy
+    Prune(CachePruneArgs),
+}
+
+#[derive(Args)]
+pub struct CacheLsArgs {
+    #[arg(short, long, help = "Optional: Name of the configuration to list")]
+    pub name: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    pub output: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct CachePruneArgs {
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    pub output: OutputFormat,
+
+    #[arg(short, long, help = "Optional: Name of the configuration to prune")]
+    pub name: Option<String>,
+
+    #[arg(long, help = "Remove every cached artifact")]
+    pub all: bool,
+
+    #[arg(long, help = "Override the configured maximum age in days")]
+    pub max_age_days: Option<u64>,
+
+    #[arg(long, help = "Override the configured maximum size in megabytes")]
+    pub max_size_mb: Option<u64>,
+}
+
+#[derive(Args)]
+pub struct InitArgs {
+    #[arg(short, long, help = "Name of the configuration to create")]
+    pub name: Option<String>,
+
+    #[arg(short, long, help = "Repository url")]
+    pub repo: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Branch to deploy, defaults to the remote's default branch"
+    )]
+    pub branch: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Absolute destination directory of the deployed files"
+    )]
+    pub to: Option<String>,
+
+    #[arg(short, long, help = "Entry point, relative to the destination")]
+    pub entry_point: Option<String>,
+
+    #[arg(short, long, help = "Take the suggested answer to every question")]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct ConfigCommandArgs {
+    #[command(subcommand)]
+    pub command: Option<ConfigCommands>,
+
+    #[arg(
+        short,
+        long,
+        required = true,
+        help = "Name of the configuration file to create"
+    )]
+    pub name: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Check configuration files and report every problem found
+    Validate(OptConfigArgs),
+
+    /// List the configurations and their state
+    List(ListArgs),
+
+    /// Print a configuration file
+    Show(ConfigArgs),
+
+    /// Open a configuration in $EDITOR and validate it before saving
+    Edit(ConfigArgs),
+
+    /// Stop and remove a configuration, its logs are archived
+    Rm(ConfigRmArgs),
+
+    /// Rename a configuration along with its pid, log, state and cache files
+    Rename(ConfigRenameArgs),
+}
+
+#[derive(Args)]
+pub struct ConfigRmArgs {
+    #[arg(short, long, help = "Name of the configuration to remove")]
+    pub name: String,
+
+    #[arg(short, long, help = "Remove without asking for confirmation")]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub struct ConfigRenameArgs {
+    #[arg(short, long, help = "Name of the configuration to rename")]
+    pub name: String,
+
+    #[arg(short, long, help = "New name of the configuration")]
+    pub to: String,
 }
 
 #[derive(Args)]
@@ -53,43 +384,499 @@ pub struct OptConfigArgs {
 
 #[derive(Args)]
 pub struct StatusArgs {
-    /// Show only repositories being actively watched
-    #[arg(short, long, help = "Filter to show only watched repositories")]
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    pub output: OutputFormat,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "output",
+        help = "Keep the status on screen and refresh it, same as flow top"
+    )]
     pub watch: bool,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[arg(short, long, value_enum, default_value_t, help = "Output format")]
+    pub output: OutputFormat,
+}
+
+#[derive(Args)]
+pub struct LogArgs {
+    #[arg(short, long, help = "Name of the configuration to show the logs of")]
+    pub name: String,
 
-    /// Show detailed process information
-    #[arg(short, long, help = "Include detailed process statistics")]
+    #[arg(
+        long,
+        conflicts_with = "watch",
+        help = "Show the output of the started app instead of the watcher log"
+    )]
     pub process: bool,
+
+    #[arg(long, help = "Show the watcher log, the default")]
+    pub watch: bool,
+
+    #[arg(short, long, help = "Keep printing lines as they are written")]
+    pub follow: bool,
+
+    #[arg(
+        short,
+        long,
+        value_name = "LINES",
+        help = "Only show the last LINES lines, 10 when following"
+    )]
+    pub tail: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Only show lines newer than a duration such as 30m, 1h or 2d, or than a date"
+    )]
+    pub since: Option<String>,
+
+    #[arg(long, help = "Only show lines at this level or a more severe one")]
+    pub level: Option<log::Level>,
+
+    #[arg(
+        long,
+        conflicts_with = "process",
+        help = "Only show the lines of the pipeline run with this id"
+    )]
+    pub run: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with = "follow",
+        help = "Output format, anything but table prints the parsed lines"
+    )]
+    pub output: OutputFormat,
+}
+
+/// Output of the listing commands, `table` for people and the others for scripts
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    Csv,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct ConfigFile {
     pub repo: String,
-    pub build: Vec<String>,
+    pub build: Vec<BuildStep>,
     pub mouve: Vec<FromTo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_build: Option<Vec<BuildStep>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_deploy: Option<Vec<BuildStep>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_deploy: Option<Vec<BuildStep>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_failure: Option<Vec<BuildStep>>,
+    /// Maximum number of steps of a stage running at the same time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_parallel: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<LogsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<Vec<NotifierConfig>>,
+    /// Environment variables given to every step, also usable in templates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<HashMap<String, String>>,
+    /// Variables only used to render templates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vars: Option<HashMap<String, String>>,
+    /// `KEY=value` file holding the secrets available to templates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub templates: Option<Vec<TemplateConfig>>,
+    /// SSH connection settings referenced by `host:path` destinations and step hosts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hosts: Option<HashMap<String, SshHost>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub entry_point: Option<Vec<Option<String>>>,
 }
+/// A build step is either a plain shell command or a full step object
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum BuildStep {
+    Command(String),
+    Step(StepConfig),
+}
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct StepConfig {
+    /// Step name, referenced by the `needs` of other steps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub run: String,
+    /// Steps that must succeed before this one starts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub needs: Option<Vec<String>>,
+    /// Maximum run time in seconds before the step's process group is killed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Working directory, relative to the repository root unless absolute
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continue_on_error: Option<bool>,
+    /// Shell used to run the command, defaults to `sh`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    /// Run the step over ssh on this host (a key of `hosts` or `[user@]hostname`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+/// Pipeline step a log line comes from, `step` counts from 1 within the stage
+#[derive(Debug, Clone, Default)]
+pub struct StepTag {
+    pub stage: String,
+    pub step: usize,
+    pub name: Option<String>,
+    pub host: Option<String>,
+}
+impl StepTag {
+    /// `build step 2`, or `build compile@web` for a named step run on a host
+    pub fn label(&self) -> String {
+        let label = match &self.name {
+            Some(name) => format!("{} {}", self.stage, name),
+            None => format!("{} step {}", self.stage, self.step),
+        };
+        match &self.host {
+            Some(host) => return format!("{}@{}", label, host),
+            None => return label,
+        }
+    }
+}
+impl BuildStep {
+    pub fn to_step(&self) -> StepConfig {
+        match self {
+            BuildStep::Command(run) => StepConfig {
+                run: run.clone(),
+                ..Default::default()
+            },
+            BuildStep::Step(step) => step.clone(),
+        }
+    }
+}
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct FromTo {
+    /// Path or glob relative to the repository root, a trailing `/` copies the contents
+    pub from: String,
+    pub to: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<SyncMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compare: Option<CompareBy>,
+    /// User and group owning the deployed files, by name or numeric id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Octal modes such as "0644", applied to the deployed files and directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir_mode: Option<String>,
+}
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Copy every file, overwriting the destination
+    #[default]
+    Copy,
+    /// Copy only the files that changed
+    Sync,
+    /// Like sync, and delete the destination files missing from the source
+    Mirror,
+}
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareBy {
+    /// Size and modification time, a fresh checkout makes every file look changed
+    Mtime,
+    /// Size and content hash
+    #[default]
+    Hash,
+}
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct SshHost {
+    pub host: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    /// Extra `-o` options given to ssh
+    pub options: Option<Vec<String>>,
+}
+/// A file rendered from a template of the repository on every deploy
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct TemplateConfig {
+    /// Template path relative to the repository root
     pub from: String,
+    /// Absolute destination path of the rendered file
     pub to: String,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub file_mode: Option<String>,
+}
+/// Artifact store settings, the build outputs listed in `mouve` are archived per commit
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct CacheConfig {
+    pub enabled: Option<bool>,
+    pub max_size_mb: Option<u64>,
+    pub max_age_days: Option<u64>,
+}
+/// Rotation of the watcher and process logs, checked by the watcher on every poll
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct LogsConfig {
+    pub max_size_mb: Option<u64>,
+    /// Rotate a log once it is this old, whatever its size
+    pub max_age_days: Option<u64>,
+    /// Number of rotated files kept next to the log
+    pub keep: Option<usize>,
+    /// Gzip the rotated files
+    pub compress: Option<bool>,
+    /// Format of the watcher log, read when the watcher starts
+    pub format: Option<LogFormat>,
+}
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[time][LEVEL] message` lines
+    #[default]
+    Text,
+    /// One JSON object per line, with the run, stage and step as fields
+    Json,
+}
+/// Where to tell about deploys and apps, only the fields of its `type` are used
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NotifierConfig {
+    #[serde(rename = "type")]
+    pub kind: NotifierKind,
+    /// Events firing the notifier, all of them when left out
+    pub events: Option<Vec<NotifyEvent>>,
+    /// Endpoint of a webhook or Slack notifier
+    pub url: Option<String>,
+    /// Template of the message, `{{ message }}`, `{{ config }}` or `{{ commit }}` for
+    /// example. A webhook sends it as its body with the values JSON escaped
+    pub body: Option<String>,
+    /// Extra headers of a webhook request
+    pub headers: Option<HashMap<String, String>>,
+    /// Mail server of an email notifier, `smtp://host:25` or `smtps://host:465`
+    pub smtp: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<Vec<String>>,
+    /// Shell command run with the event in `FLOW_*` environment variables
+    pub command: Option<String>,
+    /// Attempts after a failed one, 2 by default
+    pub retries: Option<u32>,
+    /// Seconds before the first retry, doubled on every following one
+    pub retry_delay: Option<u64>,
+}
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    /// JSON POST to `url`
+    Webhook,
+    /// Slack or Mattermost incoming webhook at `url`
+    Slack,
+    Email,
+    Command,
+}
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    DeployStarted,
+    DeploySucceeded,
+    DeployFailed,
+    /// An entry point started by `flow start` exited, noticed by the watcher
+    AppCrashed,
+    AppRestarted,
 }
-#[derive(Debug, Serialize, Deserialize, Tabled)]
+#[derive(Debug, Serialize, Deserialize, Default, Tabled)]
 pub struct WatchStats {
-    pub pid: String,
+    /// Pid of the watcher, null when it isn't running
+    #[tabled(display = "display_option")]
+    pub pid: Option<u32>,
     pub name: String,
     pub repo: String,
     pub branch: String,
-    pub cpu: String,
-    pub memory: String,
+    /// Cpu usage in percent of the watcher and its builds, along with the entry points and
+    /// their children
+    #[tabled(display = "display_cpu")]
+    pub cpu: Option<f32>,
+    /// Resident memory in bytes of the same processes as `cpu`
+    #[tabled(display = "display_memory")]
+    pub memory: Option<u64>,
+    #[tabled(display = "display_option")]
+    pub threads: Option<u64>,
+    #[tabled(rename = "files", display = "display_option")]
+    pub open_files: Option<u64>,
     pub status: String,
+    /// Seconds since the watcher started
+    #[tabled(skip)]
+    pub uptime: Option<u64>,
+    /// Number of times `flow start` restarted the entry points
+    #[tabled(skip)]
+    pub restarts: u64,
+    #[tabled(skip)]
+    pub last_deploy_sha: Option<String>,
+    /// RFC 3339 time the last deploy finished at
+    #[tabled(skip)]
+    pub last_deploy_time: Option<String>,
+    /// `success` or `failed`
+    #[tabled(skip)]
+    pub last_deploy_result: Option<String>,
 }
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SysInfo {
+#[derive(Debug, Serialize, Deserialize, Default, Tabled)]
+pub struct CacheStats {
     pub name: String,
-    pub cpu_usage: String,
-    pub memory: String,
-    pub status: String,
+    #[tabled(display = "display_short_sha")]
+    pub commit: String,
+    pub config_hash: String,
+    /// Archive size in bytes
+    #[tabled(display = "display_memory")]
+    pub size: u64,
+    /// Seconds since the archive was written
+    #[tabled(display = "display_age")]
+    pub age: u64,
+}
+#[derive(Debug, Serialize, Deserialize, Default, Tabled)]
+pub struct ConfigStats {
+    pub name: String,
+    pub repo: String,
+    pub branch: String,
+    /// Deployed commit, null until the first deploy
+    #[tabled(display = "display_version")]
+    pub version: Option<String>,
+    /// Pid of the watcher, null when it isn't running
+    #[tabled(rename = "watcher", display = "display_watcher")]
+    pub watcher_pid: Option<u32>,
+    /// Validation problems, empty when the config is valid
+    #[tabled(rename = "config", display = "display_problems")]
+    pub problems: Vec<String>,
+}
+/// An operator action, a line of the audit log
+#[derive(Debug, Serialize, Deserialize, Default, Tabled)]
+pub struct AuditEntry {
+    #[tabled(display = "display_time")]
+    pub time: String,
+    /// `cli`, or `api` for the actions asked through the HTTP API, the token tells it in tables
+    #[tabled(skip)]
+    pub source: String,
+    /// Real unix user flow ran as, followed by the one sudo says ran it
+    pub user: String,
+    /// Name of the API token the action was asked with
+    #[tabled(display = "display_option")]
+    pub token: Option<String>,
+    pub action: String,
+    #[tabled(display = "display_option")]
+    pub config: Option<String>,
+    #[tabled(display = "display_args")]
+    pub args: Vec<String>,
+    /// `success`, `failed`, `cancelled`, `unchanged`, or for the API `accepted` and `denied`
+    pub outcome: String,
+    #[tabled(display = "display_option")]
+    pub reason: Option<String>,
+}
+/// A line of a log file, split into its time, level and message when it has them.
+/// The run, stage and step are only known for JSON lines
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct LogLine {
+    pub time: Option<String>,
+    pub level: Option<String>,
+    pub run: Option<String>,
+    pub stage: Option<String>,
+    pub step: Option<u64>,
+    pub message: String,
+}
+/// Outcome of a pipeline run
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Tabled)]
+pub struct DeployRecord {
+    #[tabled(rename = "commit", display = "display_short_sha")]
+    pub sha: String,
+    pub branch: String,
+    #[tabled(rename = "run")]
+    pub run_id: String,
+    #[tabled(rename = "started", display = "display_time")]
+    pub started_at: String,
+    #[tabled(skip)]
+    pub finished_at: String,
+    #[tabled(rename = "duration", display = "display_secs")]
+    pub duration_secs: f64,
+    /// `success` or `failed`
+    pub result: String,
+    /// Stage that failed
+    #[tabled(display = "display_option")]
+    pub stage: Option<String>,
+    #[tabled(display = "display_option")]
+    pub error: Option<String>,
+    /// Outcome on each remote host, empty when every destination is local
+    #[serde(default)]
+    #[tabled(display = "display_hosts")]
+    pub hosts: Vec<HostOutcome>,
+}
+/// Outcome of a deploy on one remote host
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct HostOutcome {
+    pub host: String,
+    /// `success` or `failed`
+    pub result: String,
+    pub error: Option<String>,
+}
+/// What fast_flow remembers about a configuration between runs
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct AppState {
+    pub last_deploy: Option<DeployRecord>,
+    pub restarts: u64,
+    /// Pipeline stage the running deploy is in, null when no deploy is running
+    pub stage: Option<String>,
+    #[serde(default)]
+    pub metrics: DeployMetrics,
+    /// Latest runs, oldest first, up to `DEFAULT_HISTORY_SIZE` of them
+    #[serde(default)]
+    pub history: Vec<DeployRecord>,
+}
+/// Counters exported on the metrics endpoint, kept across watcher restarts
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct DeployMetrics {
+    pub deploys_succeeded: u64,
+    pub deploys_failed: u64,
+    /// Unix time of the last successful deploy
+    pub last_success_at: Option<i64>,
+    /// Polls that failed before a run could start, such as an unreachable repository
+    pub poll_errors: u64,
+    /// Builds per duration bucket, the buckets are `BUILD_DURATION_BUCKETS` then +Inf
+    pub build_buckets: Vec<u64>,
+    pub build_seconds_sum: f64,
+    pub build_count: u64,
+}
+/// Usage of a process tree, summed over its processes
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct ProcessUsage {
+    pub cpu_percent: f32,
+    pub memory_bytes: u64,
+    pub threads: u64,
+    pub open_files: u64,
+    pub processes: u64,
+    /// Seconds since the first root of the tree still running started
+    pub uptime_secs: u64,
 }
@@ -1,20 +1,51 @@
-use crate::utils::{
-    content::config_example,
-    structs::ConfigFile,
-    table::watch_status_table,
-    utils::{load_file_parsed, prompt_user},
+use crate::{
+    core::utils::{
+        cache::{CacheEntry, list_entries, prune_entries},
+        command::{execute_commande, prompt_user},
+        filesystem::{
+            check_dir_exist_or_create, list_dir_contents, load_file_parsed, read_from_file_ut,
+            write_to_file_ut,
+        },
+        git::extract_repo_info,
+        remote::shell_quote,
+        sampler::sample_usage,
+    },
+    utils::{
+        content::config_example,
+        structs::{
+            CacheLsArgs, CachePruneArgs, CacheStats, ConfigFile, ConfigRenameArgs, ConfigRmArgs,
+            ConfigStats, DeployArgs, DeployRequest, HistoryArgs, OutputFormat, RollbackArgs,
+            RunArgs, StatusArgs,
+        },
+        table::{create_table, watch_status_table},
+    },
+};
+use std::{
+    fs,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
-use std::{process::Command, thread, time::Duration};
 use tokio::task;
 
 use super::{
-    daemon::daemonizer,
-    structs::{SysInfo, WatchStats},
+    audit::{AUDIT_TOKEN_ENV, end_audit, fail, set_outcome, set_run_outcome},
+    daemon::{console_logger, daemonizer},
+    dry_run::dry_run,
+    metrics::start_metrics_daemon,
+    notify::{Notification, notifiers_for, notify, wait_notifications},
+    output::format_records,
+    paths::load_global_config,
+    server::start_http_daemon,
+    state::{load_state, record_restart, rollback_target},
+    structs::{NotifyEvent, WatchStats},
     utils::{
-        check_dir_exist_or_create, check_or_create_entry_point, execute_commande,
-        extract_repo_info, get_process_runner, get_sys_info, list_dir_contents, read_from_file_ut,
-        watch_config_repo, write_to_file_ut,
+        DEFAULT_CACHE_MAX_AGE_DAYS, DEFAULT_CACHE_MAX_SIZE_MB, DEFAULT_STOP_GRACE,
+        check_or_create_entry_point, deploy_config_repo, deploy_request_path, get_process_runner,
+        rotate_log, watch_config_repo,
     },
+    validate::{Diagnostic, validate_config},
 };
 
 pub fn init_config(name: String, path: &str) -> () {
@@ -22,48 +53,70 @@ pub fn init_config(name: String, path: &str) -> () {
 
     match read_from_file_ut(&config_path) {
         Ok(_) => {
-            println!("Error! the specified name already has a config check at {config_path}");
-            return;
+            return fail(format!(
+                "Error! the specified name already has a config check at {config_path}"
+            ));
         }
         Err(_err) => {}
     };
 
     match write_to_file_ut(&config_path, &config_example()) {
         Ok(_) => (),
-        Err(err) => {
-            println!("{}", err);
-        }
+        Err(err) => fail(err.to_string()),
     };
 
     println!("config file boiler plate created go edit it at {config_path} ")
 }
 
+#[allow(
+    clippy::too_many_arguments,
+    reason = "each directory of the layout is passed on its own"
+)]
 pub fn watch_repo(
+    root: Option<String>,
     work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
     process_dir: &str,
     logs_dir: &str,
     config_dir_path: &str,
     name: Option<String>,
 ) -> () {
-    let _ = check_dir_exist_or_create(&format!("{}/example", config_dir_path));
-    let _ = check_dir_exist_or_create(&format!("{}/example", work_dir));
-    let _ = check_dir_exist_or_create(&format!("{}/example", process_dir));
-    let _ = check_dir_exist_or_create(&format!("{}/example", logs_dir));
+    check_dir_exist_or_create(&format!("{}/example", config_dir_path));
+    check_dir_exist_or_create(&format!("{}/example", work_dir));
+    check_dir_exist_or_create(&format!("{}/example", process_dir));
+    check_dir_exist_or_create(&format!("{}/example", logs_dir));
+
+    let global = load_global_config();
+    if let Some(listen) = global.metrics_listen {
+        start_metrics_daemon(&listen, state_dir, process_dir, logs_dir, config_dir_path);
+    }
+    if let Some(http) = global.http {
+        start_http_daemon(
+            root,
+            &http,
+            state_dir,
+            process_dir,
+            logs_dir,
+            config_dir_path,
+        );
+    }
 
     let mut liste: Vec<String> = Vec::new();
 
     if name.is_none() {
-        liste = match list_dir_contents(&config_dir_path) {
+        liste = match list_dir_contents(config_dir_path) {
             Ok(content) => content,
             Err(err) => {
-                print!("{}", err.to_string());
+                print!("{}", err);
+                set_outcome("failed", Some(err.to_string()));
                 return;
             }
         }
     }
 
-    if name.is_some() {
-        liste.push(format!("{}.config.json", name.unwrap()));
+    if let Some(name) = &name {
+        liste.push(format!("{}.config.json", name));
     }
 
     let mut names = Vec::<String>::new();
@@ -74,12 +127,19 @@ pub fn watch_repo(
     }
 
     let work_dir = work_dir.to_owned();
+    let cache_dir = cache_dir.to_owned();
+    let state_dir = state_dir.to_owned();
     let process_dir = process_dir.to_owned();
     let logs_dir = logs_dir.to_owned();
     let config_dir_path = config_dir_path.to_owned();
 
+    // the watchers take this process over, so the action is recorded now
+    end_audit();
+
     for name in names {
         let work_dir = work_dir.clone();
+        let cache_dir = format!("{}/{}", &cache_dir, &name);
+        let state_dir = state_dir.clone();
         let process_dir = process_dir.clone();
         let logs_dir = logs_dir.clone();
         let config_dir_path = config_dir_path.clone();
@@ -93,6 +153,8 @@ pub fn watch_repo(
             daemonizer(
                 name,
                 &work_dir,
+                &cache_dir,
+                &state_dir,
                 &config_file_path,
                 &pid_file_path,
                 &log_file_path,
@@ -102,32 +164,173 @@ pub fn watch_repo(
     }
 }
 
+pub fn run_pipeline(
+    work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
+    process_dir: &str,
+    config_dir_path: &str,
+    args: RunArgs,
+) -> () {
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, &args.name);
+
+    if read_from_file_ut(&config_file_path).is_err() {
+        return fail(format!(
+            "no config named [{}] at {}",
+            &args.name, &config_file_path
+        ));
+    }
+
+    if args.dry_run {
+        return dry_run(
+            cache_dir,
+            process_dir,
+            config_dir_path,
+            &args.name,
+            args.run_build,
+        );
+    }
+
+    check_dir_exist_or_create(&format!("{}/example", work_dir));
+    console_logger(&config_file_path);
+    let before = load_state(state_dir, &args.name);
+    watch_config_repo(
+        work_dir,
+        &format!("{}/{}", cache_dir, &args.name),
+        state_dir,
+        &config_file_path,
+    );
+    set_run_outcome(&before, &load_state(state_dir, &args.name));
+    wait_notifications();
+}
+
+pub fn deploy(
+    work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
+    process_dir: &str,
+    config_dir_path: &str,
+    args: DeployArgs,
+) -> () {
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, &args.name);
+
+    if read_from_file_ut(&config_file_path).is_err() {
+        return fail(format!(
+            "no config named [{}] at {}",
+            &args.name, &config_file_path
+        ));
+    }
+
+    let request = DeployRequest {
+        git_ref: args.git_ref,
+        force: args.force,
+        poll: false,
+    };
+
+    if args.daemon {
+        let watching = watcher_pid(process_dir, &args.name).is_some();
+
+        let request_path = deploy_request_path(work_dir, &config_file_path);
+        check_dir_exist_or_create(&request_path);
+
+        match fs::write(&request_path, serde_json::to_string(&request).unwrap()) {
+            Ok(_) => {
+                println!("deploy of [{}] handed to the watcher", &args.name);
+                set_outcome("accepted", Some("handed to the watcher".to_string()));
+            }
+            Err(err) => return fail(err.to_string()),
+        }
+
+        if !watching {
+            println!(
+                "no watcher is running for [{}], start one with flow watch -n {}",
+                &args.name, &args.name
+            );
+        }
+        return;
+    }
+
+    // own checkout dir so a running watcher doesn't clone over this deploy
+    let work_dir = format!("{}/deploy", work_dir);
+    check_dir_exist_or_create(&format!("{}/example", &work_dir));
+    console_logger(&config_file_path);
+    let before = load_state(state_dir, &args.name);
+    deploy_config_repo(
+        &work_dir,
+        &format!("{}/{}", cache_dir, &args.name),
+        state_dir,
+        &config_file_path,
+        &request,
+    );
+    set_run_outcome(&before, &load_state(state_dir, &args.name));
+    wait_notifications();
+}
+
+pub fn rollback(
+    work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
+    process_dir: &str,
+    config_dir_path: &str,
+    args: RollbackArgs,
+) -> () {
+    let target = match args.to {
+        Some(to) => to,
+        None => match rollback_target(state_dir, &args.name) {
+            Some(target) => target,
+            None => {
+                return fail(format!(
+                    "[{}] has no earlier successful deploy of another commit to roll back to",
+                    &args.name
+                ));
+            }
+        },
+    };
+
+    println!("rolling [{}] back to {}", &args.name, &target);
+    deploy(
+        work_dir,
+        cache_dir,
+        state_dir,
+        process_dir,
+        config_dir_path,
+        DeployArgs {
+            name: args.name,
+            git_ref: Some(target),
+            force: true,
+            daemon: args.daemon,
+        },
+    );
+}
+
 pub fn run_flow(
     work_dir: &str,
+    state_dir: &str,
     process_dir: &str,
     logs_dir: &str,
     config_dir_path: &str,
     name: Option<String>,
 ) -> () {
-    let _ = check_dir_exist_or_create(&format!("{}/example", config_dir_path));
-    let _ = check_dir_exist_or_create(&format!("{}/example", work_dir));
-    let _ = check_dir_exist_or_create(&format!("{}/example", process_dir));
-    let _ = check_dir_exist_or_create(&format!("{}/example", logs_dir));
+    check_dir_exist_or_create(&format!("{}/example", config_dir_path));
+    check_dir_exist_or_create(&format!("{}/example", work_dir));
+    check_dir_exist_or_create(&format!("{}/example", process_dir));
+    check_dir_exist_or_create(&format!("{}/example", logs_dir));
 
     let mut liste: Vec<String> = Vec::new();
 
     if name.is_none() {
-        liste = match list_dir_contents(&config_dir_path) {
+        liste = match list_dir_contents(config_dir_path) {
             Ok(content) => content,
             Err(err) => {
-                print!("{}", err.to_string());
+                print!("{}", err);
+                set_outcome("failed", Some(err.to_string()));
                 return;
             }
         }
     }
 
-    if name.is_some() {
-        liste.push(format!("{}.config.json", name.unwrap()));
+    if let Some(name) = &name {
+        liste.push(format!("{}.config.json", name));
     }
 
     let mut names = Vec::<String>::new();
@@ -137,13 +340,11 @@ pub fn run_flow(
         names.push(r[0].to_string());
     }
 
-    let work_dir = work_dir.to_owned();
     let process_dir = process_dir.to_owned();
     let logs_dir = logs_dir.to_owned();
     let config_dir_path = config_dir_path.to_owned();
 
     for name in names {
-        let work_dir = work_dir.clone();
         let process_dir = process_dir.clone();
         let logs_dir = logs_dir.clone();
         let config_dir_path = config_dir_path.clone();
@@ -152,11 +353,18 @@ pub fn run_flow(
         let pid_file_path = format!("{}/{}.process.pid", &process_dir, &name);
         let log_file_path = format!("{}/{}.process.log", &logs_dir, &name);
 
-        let mut config = match load_file_parsed::<ConfigFile>(&config_file_path) {
-            Ok(conf) => conf,
+        let content = match fs::read_to_string(&config_file_path) {
+            Ok(content) => content,
             Err(err) => {
-                println!("err loading");
-                println!("{err}");
+                return fail(format!("err loading {} : {}", &config_file_path, err));
+            }
+        };
+
+        let mut config = match validate_config(&content, false) {
+            Ok(conf) => conf,
+            Err(diagnostics) => {
+                print_diagnostics(&name, &config_file_path, &diagnostics);
+                set_outcome("failed", Some(format!("invalid config [{}]", &name)));
                 return;
             }
         };
@@ -165,62 +373,118 @@ pub fn run_flow(
             Ok(_) => {
                 println!("Entry point(s) saved to config");
             }
-            Err(err) => {
-                println!("{}", err);
-                return;
-            }
+            Err(err) => return fail(err.to_string()),
         };
 
-        for entry in config.entry_point.unwrap() {
-            if entry.as_ref().is_none() {
-                continue;
+        // a start replaces the entry points still running from an earlier one
+        let previous = process_pids(&process_dir, &name);
+        for (pid, stopped) in stop_processes(&process_dir, &name) {
+            if stopped {
+                println!("Stopped the earlier {name} process {pid}");
             }
+        }
+        wait_stopped(&previous);
 
-            let runner =
-                match get_process_runner(&entry.as_ref().unwrap().split("/").last().unwrap()) {
-                    Ok(run) => run,
-                    Err(err) => {
-                        println!("{err}");
-                        return;
-                    }
-                };
+        // apps still running from an earlier start keep the log open
+        if let Err(err) = rotate_log(
+            &log_file_path,
+            &config.logs.clone().unwrap_or_default(),
+            true,
+        ) {
+            println!("could not rotate {} : {}", &log_file_path, err);
+        }
+
+        let mut pids = Vec::<String>::new();
+
+        for entry in config.entry_point.unwrap() {
+            let entry = match entry {
+                Some(e) => e,
+                None => continue,
+            };
+
+            let runner = match get_process_runner(entry.split("/").last().unwrap()) {
+                Ok(run) => run,
+                Err(err) => return fail(err.to_string()),
+            };
+
+            let log_file = match fern::log_file(&log_file_path) {
+                Ok(f) => f,
+                Err(err) => return fail(err.to_string()),
+            };
+            let err_file = match log_file.try_clone() {
+                Ok(f) => f,
+                Err(err) => return fail(err.to_string()),
+            };
 
-            let output = Command::new(entry.unwrap())
-                .arg("-l")
-                .arg("-a")
-                .output()
-                .expect("Failed to execute command");
+            // files without an extension are binaries and are executed directly
+            let mut command = match entry.split("/").last().unwrap().contains(".") {
+                true => {
+                    let mut cmd = Command::new(&runner);
+                    cmd.arg(&entry);
+                    cmd
+                }
+                false => Command::new(&entry),
+            };
 
-            // Print command output
-            println!("Status: {}", output.status);
-            println!("Stdout: {}", String::from_utf8_lossy(&output.stdout));
-            println!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
+            match command
+                .stdout(log_file)
+                .stderr(err_file)
+                .process_group(0)
+                .spawn()
+            {
+                Ok(child) => {
+                    println!("Started {entry} with pid {}", child.id());
+                    pids.push(child.id().to_string());
+                }
+                Err(err) => fail(format!("Failed to start {entry} : {err}")),
+            }
+        }
+
+        if !pids.is_empty() {
+            // a pid file left by an earlier start makes this a restart
+            if read_from_file_ut(&pid_file_path).is_ok() {
+                record_restart(state_dir, &name);
+                notify(
+                    &notifiers_for(config.notify.clone()),
+                    Notification::for_app(
+                        NotifyEvent::AppRestarted,
+                        &name,
+                        format!("entry points of [{}] restarted", &name),
+                    ),
+                );
+            }
+            let _ = execute_commande(&format!("rm -f {}", &pid_file_path));
+            let _ = write_to_file_ut(&pid_file_path, &pids.join("\n"));
         }
     }
+
+    wait_notifications();
 }
 
 pub fn stop_all_track(process_dir: &str, name: Option<String>, silent: bool) -> () {
     let mut liste: Vec<String> = Vec::new();
 
     if name.is_none() {
-        liste = match list_dir_contents(&process_dir) {
+        liste = match list_dir_contents(process_dir) {
             Ok(content) => content,
             Err(err) => {
-                if silent == true {
+                set_outcome("failed", Some(err.to_string()));
+                if silent {
                     return;
                 }
-                println!("{}", err.to_string());
+                println!("{}", err);
                 return;
             }
         };
     }
 
-    if name.is_some() {
-        liste.push(format!("{}.watch.pid", name.unwrap()));
+    if let Some(name) = &name {
+        liste.push(format!("{}.watch.pid", name));
     }
 
-    if liste.len() == 0 {
-        if silent == true {
+    if liste.is_empty() {
+        set_outcome("unchanged", None);
+        if silent {
             return;
         }
 
@@ -228,7 +492,7 @@ pub fn stop_all_track(process_dir: &str, name: Option<String>, silent: bool) ->
         return;
     }
 
-    if silent != true {
+    if !silent {
         println!("Shutting down {} Runing processes", &liste.len());
     }
 
@@ -236,7 +500,7 @@ pub fn stop_all_track(process_dir: &str, name: Option<String>, silent: bool) ->
         let tmp_pid = match read_from_file_ut(&format!("{}/{}", &process_dir, &elem)) {
             Ok(content) => content,
             Err(err) => {
-                println!("{}", err.to_string());
+                println!("{}", err);
                 continue;
             }
         };
@@ -244,74 +508,45 @@ pub fn stop_all_track(process_dir: &str, name: Option<String>, silent: bool) ->
         let _ = execute_commande(&format!("rm -rf {}/{}", &process_dir, &elem));
     }
 
-    if silent != true {
+    if !silent {
         println!("all your repositories tracking was terminated");
     }
 
     return;
 }
 
-pub fn show_logs(logs_dir: &str, name: String) -> () {
-    loop {
-        let file_content = match read_from_file_ut(&format!("{}/{}.watch.log", &logs_dir, &name)) {
-            Ok(cont) => {
-                // console.clear_screen().unwrap();
-                // print!("\x1B[2J\x1B[1;1H");
-                print!("{esc}c", esc = 27 as char);
-                cont
-            }
-            Err(err) => {
-                println!("{err}");
-                return;
-            }
-        };
-
-        // let _ = console.write_line(&format!("{}", file_content));
-        println!("{file_content}");
-        thread::sleep(Duration::from_secs(5));
-    }
-}
-
-pub fn show_status(process_dir: &str, _logs_dir: &str, config_dir_path: &str) -> () {
-    let liste = match list_dir_contents(&config_dir_path) {
-        Ok(content) => content,
-        Err(err) => {
-            println!("{}", err.to_string());
-            return;
-        }
-    };
-
-    if liste.len() == 0 {
-        println!("You have No Repositorys being watched right now!");
-        return;
-    }
+/// Watch status of every configuration
+pub fn watch_stats(
+    state_dir: &str,
+    process_dir: &str,
+    config_dir_path: &str,
+) -> Result<Vec<WatchStats>, String> {
+    // checked first since list_dir_contents prints its errors to stdout
+    fs::read_dir(config_dir_path).map_err(|err| format!("{} : {}", config_dir_path, err))?;
+    let liste = list_dir_contents(config_dir_path).map_err(|err| err.to_string())?;
     let mut data: Vec<WatchStats> = Vec::new();
+    let mut roots: Vec<Vec<u32>> = Vec::new();
 
     for file_name in liste {
         let config =
             match load_file_parsed::<ConfigFile>(&format!("{}/{}", &config_dir_path, &file_name)) {
                 Ok(conf) => conf,
                 Err(err) => {
-                    println!("{}", err);
+                    eprintln!("{}", err);
                     continue;
                 }
             };
 
-        let ConfigFile {
-            repo,
-            build: _,
-            mouve: _,
-            branch,
-            version: _,
-            entry_point: _,
-        } = config;
+        let ConfigFile { repo, branch, .. } = config;
 
         // Extract the user name and repo
         let repo_info = match extract_repo_info(&repo) {
             Some(rep) => rep,
             None => {
-                println!("error while parsing your github repo to extract the name, check it");
-                return;
+                return Err(
+                    "error while parsing your github repo to extract the name, check it"
+                        .to_string(),
+                );
             }
         };
 
@@ -320,51 +555,727 @@ pub fn show_status(process_dir: &str, _logs_dir: &str, config_dir_path: &str) ->
 
         let name: Vec<&str> = file_name.split(".").collect();
 
-        let pid = match read_from_file_ut(&format!("{}/{}.watch.pid", &process_dir, &name[0])) {
-            Ok(content) => content.trim().to_string(),
-            Err(_err) => {
-                let data_elem = WatchStats {
-                    name: format!("{}", name[0]),
-                    pid: "N/A".to_string(),
-                    repo: format!("{username}/{folder_name}.git"),
-                    branch: branch.unwrap_or("main".to_string()),
-                    cpu: "N/A".to_string(),
-                    memory: "N/A".to_string(),
-                    status: "unwatched".to_string(),
-                };
-
-                data.push(data_elem);
+        let state = load_state(state_dir, name[0]);
+        let last_deploy = state.last_deploy;
+
+        let mut data_elem = WatchStats {
+            name: name[0].to_string(),
+            pid: None,
+            repo: format!("{username}/{folder_name}.git"),
+            branch: branch.unwrap_or("main".to_string()),
+            cpu: None,
+            memory: None,
+            threads: None,
+            open_files: None,
+            status: "unwatched".to_string(),
+            uptime: None,
+            restarts: state.restarts,
+            last_deploy_sha: last_deploy.as_ref().map(|deploy| deploy.sha.clone()),
+            last_deploy_time: last_deploy
+                .as_ref()
+                .map(|deploy| deploy.finished_at.clone()),
+            last_deploy_result: last_deploy.as_ref().map(|deploy| deploy.result.clone()),
+        };
+
+        // a pid file whose process is gone counts as unwatched
+        data_elem.pid = watcher_pid(process_dir, name[0]).and_then(|pid| pid.parse().ok());
+        if data_elem.pid.is_some() {
+            data_elem.status = "watched".to_string();
+        }
+
+        // the watcher with its builds, and the entry points with their children
+        let mut app_roots: Vec<u32> = data_elem.pid.into_iter().collect();
+        app_roots.extend(process_pids(process_dir, name[0]));
+        roots.push(app_roots);
+
+        data.push(data_elem);
+    }
+
+    for (data_elem, usage) in data.iter_mut().zip(sample_usage(&roots)) {
+        if let Some(usage) = usage {
+            data_elem.cpu = Some(usage.cpu_percent);
+            data_elem.memory = Some(usage.memory_bytes);
+            data_elem.threads = Some(usage.threads);
+            data_elem.open_files = Some(usage.open_files);
+            if data_elem.pid.is_some() {
+                data_elem.uptime = Some(usage.uptime_secs);
+            }
+        }
+    }
+
+    return Ok(data);
+}
+
+pub fn show_status(
+    state_dir: &str,
+    process_dir: &str,
+    _logs_dir: &str,
+    config_dir_path: &str,
+    args: StatusArgs,
+) -> () {
+    let data = match watch_stats(state_dir, process_dir, config_dir_path) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if data.is_empty() && args.output == OutputFormat::Table {
+        println!("You have No Repositorys being watched right now!");
+        return;
+    }
+
+    match args.output {
+        OutputFormat::Table => {
+            let table = watch_status_table(data, "Fast⚡Flow Watching Status");
+            println!("{table}");
+        }
+        output => println!("{}", format_records(&data, output)),
+    }
+}
+
+pub fn show_history(state_dir: &str, config_dir_path: &str, args: HistoryArgs) -> () {
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, &args.name);
+
+    if read_from_file_ut(&config_file_path).is_err() {
+        eprintln!("no config named [{}] at {}", &args.name, &config_file_path);
+        std::process::exit(1);
+    }
+
+    let mut data = load_state(state_dir, &args.name).history;
+    data.reverse();
+
+    if args.output != OutputFormat::Table {
+        println!("{}", format_records(&data, args.output));
+        return;
+    }
+
+    if data.is_empty() {
+        println!("[{}] was not deployed yet", &args.name);
+        return;
+    }
+
+    let table = create_table(&data, "Fast⚡Flow Deploy History");
+    println!("{table}");
+}
+
+fn cached_config_names(cache_dir: &str, name: Option<String>) -> Vec<String> {
+    if let Some(name) = name {
+        return vec![name];
+    }
+
+    let dir_content = match fs::read_dir(cache_dir) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut names: Vec<String> = dir_content
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+
+    return names;
+}
+
+fn cache_stats(name: &str, entry: &CacheEntry) -> CacheStats {
+    let (commit, config_hash) = match entry.key.rsplit_once("-") {
+        Some((commit, hash)) => (commit.to_string(), hash.to_string()),
+        None => (entry.key.clone(), String::new()),
+    };
+
+    return CacheStats {
+        name: name.to_string(),
+        commit,
+        config_hash,
+        size: entry.size,
+        age: entry.age.as_secs(),
+    };
+}
+
+pub fn cache_list(cache_dir: &str, args: CacheLsArgs) -> () {
+    let mut data: Vec<CacheStats> = Vec::new();
+
+    for name in cached_config_names(cache_dir, args.name) {
+        let entries = match list_entries(&format!("{}/{}", cache_dir, &name)) {
+            Ok(entries) => entries,
+            Err(err) => {
+                println!("{name} : {err}");
                 continue;
             }
         };
 
-        let sys_info = match get_sys_info(&pid) {
-            Ok(sy) => sy,
+        for entry in entries {
+            data.push(cache_stats(&name, &entry));
+        }
+    }
+
+    if args.output != OutputFormat::Table {
+        println!("{}", format_records(&data, args.output));
+        return;
+    }
+
+    if data.is_empty() {
+        println!("The artifact cache is empty");
+        return;
+    }
+
+    let table = create_table(&data, "Fast⚡Flow Artifact Cache");
+    println!("{table}");
+}
+
+pub fn cache_prune(cache_dir: &str, config_dir_path: &str, args: CachePruneArgs) -> () {
+    let mut data: Vec<CacheStats> = Vec::new();
+
+    for name in cached_config_names(cache_dir, args.name) {
+        let cache =
+            load_file_parsed::<ConfigFile>(&format!("{}/{}.config.json", config_dir_path, &name))
+                .ok()
+                .and_then(|config| config.cache)
+                .unwrap_or_default();
+
+        let (max_age, max_size) = match args.all {
+            true => (Some(Duration::ZERO), Some(0)),
+            false => (
+                Some(Duration::from_secs(
+                    args.max_age_days
+                        .or(cache.max_age_days)
+                        .unwrap_or(DEFAULT_CACHE_MAX_AGE_DAYS)
+                        * 24
+                        * 3600,
+                )),
+                Some(
+                    args.max_size_mb
+                        .or(cache.max_size_mb)
+                        .unwrap_or(DEFAULT_CACHE_MAX_SIZE_MB)
+                        * 1_000_000,
+                ),
+            ),
+        };
+
+        match prune_entries(&format!("{}/{}", cache_dir, &name), max_age, max_size) {
+            Ok(removed) => {
+                for entry in removed {
+                    data.push(cache_stats(&name, &entry));
+                }
+            }
+            Err(err) => println!("{name} : {err}"),
+        }
+    }
+
+    if args.output != OutputFormat::Table {
+        println!("{}", format_records(&data, args.output));
+        return;
+    }
+
+    if data.is_empty() {
+        println!("Nothing to prune");
+        return;
+    }
+
+    let table = create_table(&data, "Fast⚡Flow Pruned Artifacts");
+    println!("{table}");
+}
+
+pub fn validate_configs(config_dir_path: &str, name: Option<String>) -> () {
+    let liste = match name {
+        Some(name) => vec![format!("{}.config.json", name)],
+        None => match list_dir_contents(config_dir_path) {
+            Ok(content) => content,
             Err(err) => {
                 println!("{}", err);
+                return;
+            }
+        },
+    };
+
+    let mut invalid = 0;
+
+    for elem in liste {
+        let name = elem.split(".").next().unwrap_or("").to_string();
+        let config_file_path = format!("{}/{}", config_dir_path, &elem);
+
+        let content = match fs::read_to_string(&config_file_path) {
+            Ok(content) => content,
+            Err(err) => {
+                println!("✖ [{}] {} : {}", &name, &config_file_path, err);
+                invalid += 1;
                 continue;
             }
         };
 
-        let SysInfo {
-            name: _,
-            cpu_usage,
-            memory,
-            status: _,
-        } = sys_info;
+        match validate_config(&content, true) {
+            Ok(_) => println!("✔ [{}] is valid", &name),
+            Err(diagnostics) => {
+                invalid += 1;
+                print_diagnostics(&name, &config_file_path, &diagnostics);
+            }
+        }
+    }
 
-        let data_elem = WatchStats {
-            name: format!("{}", name[0]),
-            pid,
-            repo: format!("{username}/{folder_name}.git"),
-            branch: branch.unwrap_or("main".to_string()),
-            cpu: cpu_usage,
-            memory: memory,
-            status: "watched".to_string(),
+    if invalid != 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Pid of the watcher of `name` when it is running
+pub fn watcher_pid(process_dir: &str, name: &str) -> Option<String> {
+    let pid = read_from_file_ut(&format!("{}/{}.watch.pid", process_dir, name)).ok()?;
+    let pid = pid.trim().to_string();
+
+    match !pid.is_empty() && fs::metadata(format!("/proc/{}", &pid)).is_ok() {
+        true => return Some(pid),
+        false => return None,
+    }
+}
+
+/// Pids of the entry points started for `name`, running or not
+pub fn process_pids(process_dir: &str, name: &str) -> Vec<u32> {
+    return read_from_file_ut(&format!("{}/{}.process.pid", process_dir, name))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|pid| pid.trim().parse::<u32>().ok())
+        .collect();
+}
+
+/// Stops the entry points started for `name`, telling for every pid whether it was running
+pub fn stop_processes(process_dir: &str, name: &str) -> Vec<(String, bool)> {
+    let pid_file_path = format!("{}/{}.process.pid", process_dir, name);
+    let pids = read_from_file_ut(&pid_file_path).unwrap_or_default();
+
+    // emptied rather than removed, so the watcher doesn't take the stop for a crash and
+    // the next start still counts as a restart
+    if !pids.is_empty() {
+        let _ = fs::write(&pid_file_path, "");
+    }
+
+    return pids
+        .lines()
+        .map(|pid| pid.trim())
+        .filter(|pid| !pid.is_empty())
+        .map(|pid| {
+            // entry points run in their own process group
+            let stopped = execute_commande(&format!("kill -TERM -{}", pid)).is_ok();
+            (pid.to_string(), stopped)
+        })
+        .collect();
+}
+
+/// Waits for the stopped entry points to exit, their process groups are killed once
+/// DEFAULT_STOP_GRACE seconds are up so a restart doesn't race them for ports or files
+fn wait_stopped(pids: &[u32]) -> () {
+    let deadline = Instant::now() + Duration::from_secs(DEFAULT_STOP_GRACE);
+    let running = |pid: &&u32| fs::metadata(format!("/proc/{}", pid)).is_ok();
+
+    while pids.iter().any(|pid| running(&pid)) {
+        if Instant::now() >= deadline {
+            for pid in pids.iter().filter(running) {
+                println!(
+                    "{} did not stop in {}s, killing it",
+                    pid, DEFAULT_STOP_GRACE
+                );
+                unsafe { libc::kill(-(*pid as i32), libc::SIGKILL) };
+            }
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Whether `name` has an entry point set, `flow start` asks for the missing ones
+pub fn has_entry_point(config_dir_path: &str, name: &str) -> bool {
+    return load_file_parsed::<ConfigFile>(&format!("{}/{}.config.json", config_dir_path, name))
+        .ok()
+        .and_then(|config| config.entry_point)
+        .is_some_and(|entries| entries.iter().any(|entry| entry.is_some()));
+}
+
+/// Runs a flow subcommand in the background with the same `--root`, its output goes to
+/// `log_file` when given. `token` names the API token it runs for in the audit log
+pub fn spawn_flow(
+    root: &Option<String>,
+    args: &[&str],
+    log_file: Option<&str>,
+    token: Option<&str>,
+) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|err| err.to_string())?;
+
+    let mut command = Command::new(exe);
+    if let Some(root) = root {
+        command.arg("--root").arg(root);
+    }
+    match token {
+        Some(token) => command.env(AUDIT_TOKEN_ENV, token),
+        None => command.env_remove(AUDIT_TOKEN_ENV),
+    };
+    command.args(args).stdin(Stdio::null());
+
+    match log_file {
+        Some(path) => {
+            let file = fern::log_file(path).map_err(|err| err.to_string())?;
+            let err_file = file.try_clone().map_err(|err| err.to_string())?;
+            command.stdout(file).stderr(err_file);
+        }
+        None => {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+    }
+
+    let mut child = command.spawn().map_err(|err| err.to_string())?;
+    thread::spawn(move || child.wait());
+
+    return Ok(());
+}
+
+/// Files of `dir` belonging to the configuration `name`, such as `name.watch.log`
+fn associated_files(dir: &str, name: &str) -> Vec<String> {
+    return list_dir_contents(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|file| file.split(".").next() == Some(name))
+        .collect();
+}
+
+fn print_diagnostics(name: &str, config_file_path: &str, diagnostics: &[Diagnostic]) -> () {
+    println!("✖ [{}] {} :", name, config_file_path);
+    for diagnostic in diagnostics {
+        println!("    {}", diagnostic);
+    }
+}
+
+/// Every configuration with the problems found in it
+pub fn config_stats(process_dir: &str, config_dir_path: &str) -> Result<Vec<ConfigStats>, String> {
+    let liste = list_dir_contents(config_dir_path).map_err(|err| err.to_string())?;
+    let mut data: Vec<ConfigStats> = Vec::new();
+
+    for file_name in liste {
+        let name = file_name.split(".").next().unwrap_or("").to_string();
+        let content =
+            fs::read_to_string(format!("{}/{}", config_dir_path, &file_name)).unwrap_or_default();
+
+        let (config, problems) = match validate_config(&content, false) {
+            Ok(config) => (Some(config), Vec::new()),
+            Err(diagnostics) => (
+                serde_json::from_str::<ConfigFile>(&content).ok(),
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.to_string())
+                    .collect(),
+            ),
         };
+        let config = config.unwrap_or_default();
 
-        data.push(data_elem);
+        data.push(ConfigStats {
+            watcher_pid: watcher_pid(process_dir, &name).and_then(|pid| pid.parse().ok()),
+            name,
+            repo: match config.repo.len() {
+                0 => "N/A".to_string(),
+                _ => config.repo,
+            },
+            branch: config.branch.unwrap_or("main".to_string()),
+            version: config
+                .version
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+            problems,
+        });
     }
-    let table = watch_status_table(data, "Fast⚡Flow Watching Status");
+
+    return Ok(data);
+}
+
+pub fn config_list(process_dir: &str, config_dir_path: &str, output: OutputFormat) -> () {
+    let data = match config_stats(process_dir, config_dir_path) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+
+    if data.is_empty() && output == OutputFormat::Table {
+        println!("You have no configuration yet, create one with flow config -n <name>");
+        return;
+    }
+
+    if output != OutputFormat::Table {
+        println!("{}", format_records(&data, output));
+        return;
+    }
+
+    let table = create_table(&data, "Fast⚡Flow Configurations");
     println!("{table}");
 }
+
+pub fn config_show(config_dir_path: &str, name: String) -> () {
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, &name);
+
+    let content = match fs::read_to_string(&config_file_path) {
+        Ok(content) => content,
+        Err(err) => {
+            println!(
+                "no config named [{}] at {} : {}",
+                &name, &config_file_path, err
+            );
+            return;
+        }
+    };
+
+    println!("{}", content.trim_end());
+
+    if let Err(diagnostics) = validate_config(&content, false) {
+        println!();
+        print_diagnostics(&name, &config_file_path, &diagnostics);
+    }
+}
+
+pub fn config_edit(config_dir_path: &str, name: String) -> () {
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, &name);
+
+    let original = match fs::read_to_string(&config_file_path) {
+        Ok(content) => content,
+        Err(_) => {
+            return fail(format!(
+                "no config named [{name}], create it with flow config -n {name}"
+            ));
+        }
+    };
+
+    let editor = std::env::var("VISUAL")
+        .or(std::env::var("EDITOR"))
+        .unwrap_or("vi".to_string());
+
+    // edit a copy so the watcher never loads a half written config
+    let tmp_path = std::env::temp_dir()
+        .join(format!("fast_flow-{}.config.json", &name))
+        .to_string_lossy()
+        .to_string();
+
+    if let Err(err) = fs::write(&tmp_path, &original) {
+        return fail(err.to_string());
+    }
+
+    loop {
+        match Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} {}", &editor, shell_quote(&tmp_path)))
+            .status()
+        {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                println!("{} exited with {}, changes discarded", &editor, status);
+                set_outcome(
+                    "cancelled",
+                    Some(format!("{} exited with {}", &editor, status)),
+                );
+                break;
+            }
+            Err(err) => {
+                fail(format!("could not open {} : {}", &editor, err));
+                break;
+            }
+        }
+
+        let content = fs::read_to_string(&tmp_path).unwrap_or_default();
+
+        if content == original {
+            println!("No changes made to [{}]", &name);
+            set_outcome("unchanged", None);
+            break;
+        }
+
+        match validate_config(&content, false) {
+            Ok(_) => {
+                match fs::write(&config_file_path, &content) {
+                    Ok(_) => println!("✔ [{}] saved to {}", &name, &config_file_path),
+                    Err(err) => fail(err.to_string()),
+                }
+                break;
+            }
+            Err(diagnostics) => {
+                print_diagnostics(&name, &config_file_path, &diagnostics);
+
+                let answer = prompt_user("Edit again ? [Y/n]").unwrap_or_default();
+                if answer == "n" || answer == "no" {
+                    println!("changes discarded");
+                    set_outcome("cancelled", Some("invalid config discarded".to_string()));
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = fs::remove_file(&tmp_path);
+}
+
+pub fn config_remove(
+    work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
+    process_dir: &str,
+    logs_dir: &str,
+    config_dir_path: &str,
+    args: ConfigRmArgs,
+) -> () {
+    let name = args.name;
+    let config_file_path = format!("{}/{}.config.json", config_dir_path, &name);
+
+    if read_from_file_ut(&config_file_path).is_err() {
+        return fail(format!(
+            "no config named [{}] at {}",
+            &name, &config_file_path
+        ));
+    }
+
+    if !args.yes {
+        let answer = prompt_user(&format!(
+            "Remove [{name}], stop its watcher and processes ? [y/N]"
+        ))
+        .unwrap_or_default();
+
+        if answer != "y" && answer != "yes" {
+            println!("nothing removed");
+            return set_outcome("cancelled", None);
+        }
+    }
+
+    // watcher first so it can't restart a deploy while the rest is removed
+    stop_all_track(process_dir, Some(name.clone()), true);
+    set_outcome("success", None);
+
+    for (pid, stopped) in stop_processes(process_dir, &name) {
+        match stopped {
+            true => println!("stopped process {}", pid),
+            false => println!("process {} was not running", pid),
+        }
+    }
+
+    let logs = associated_files(logs_dir, &name);
+    if !logs.is_empty() {
+        let archive_dir = format!("{}/archive", logs_dir);
+        check_dir_exist_or_create(&format!("{}/example", &archive_dir));
+        let archive = format!(
+            "{}/{}-{}.tar.gz",
+            &archive_dir,
+            &name,
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        );
+
+        match execute_commande(&format!(
+            "tar -czf {} -C {} {}",
+            shell_quote(&archive),
+            shell_quote(logs_dir),
+            logs.iter()
+                .map(|log| shell_quote(log))
+                .collect::<Vec<String>>()
+                .join(" ")
+        )) {
+            Ok(_) => {
+                for log in &logs {
+                    let _ = fs::remove_file(format!("{}/{}", logs_dir, log));
+                }
+                println!("logs archived to {}", &archive);
+            }
+            Err(err) => println!("logs kept, could not archive them : {}", err),
+        }
+    }
+
+    for dir in [process_dir, work_dir, state_dir, config_dir_path] {
+        for file in associated_files(dir, &name) {
+            let _ = fs::remove_file(format!("{}/{}", dir, file));
+        }
+    }
+    let _ = fs::remove_dir_all(format!("{}/{}", cache_dir, &name));
+
+    println!("[{}] removed", &name);
+}
+
+#[allow(
+    clippy::too_many_arguments,
+    reason = "each directory of the layout is passed on its own"
+)]
+pub fn config_rename(
+    root: Option<String>,
+    work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
+    process_dir: &str,
+    logs_dir: &str,
+    config_dir_path: &str,
+    args: ConfigRenameArgs,
+) -> () {
+    let ConfigRenameArgs { name, to } = args;
+
+    if to.is_empty() || to.contains(".") || to.contains("/") {
+        return fail(format!(
+            "`{}` is not a valid name, it can't be empty or contain `.` or `/`",
+            &to
+        ));
+    }
+    if read_from_file_ut(&format!("{}/{}.config.json", config_dir_path, &name)).is_err() {
+        return fail(format!("no config named [{}]", &name));
+    }
+    if read_from_file_ut(&format!("{}/{}.config.json", config_dir_path, &to)).is_ok() {
+        return fail(format!("a config named [{}] already exists", &to));
+    }
+
+    // the watcher holds the old paths, it is restarted under the new name
+    let was_watching = watcher_pid(process_dir, &name).is_some();
+    if was_watching {
+        stop_all_track(process_dir, Some(name.clone()), true);
+    }
+    let mut failures = Vec::<String>::new();
+
+    for dir in [config_dir_path, process_dir, logs_dir, work_dir, state_dir] {
+        for file in associated_files(dir, &name) {
+            let renamed = format!("{}{}", &to, &file[name.len()..]);
+            match fs::rename(
+                format!("{}/{}", dir, &file),
+                format!("{}/{}", dir, &renamed),
+            ) {
+                Ok(_) => println!("{}/{} -> {}", dir, &file, &renamed),
+                Err(err) => {
+                    println!("could not rename {}/{} : {}", dir, &file, err);
+                    failures.push(format!("{}/{} : {}", dir, &file, err));
+                }
+            }
+        }
+    }
+
+    let old_cache = format!("{}/{}", cache_dir, &name);
+    if fs::metadata(&old_cache).is_ok()
+        && let Err(err) = fs::rename(&old_cache, format!("{}/{}", cache_dir, &to))
+    {
+        println!("could not rename {} : {}", &old_cache, err);
+        failures.push(format!("{} : {}", &old_cache, err));
+    }
+
+    // set once every file was moved, a restarted watcher failing still wins
+    match failures.len() {
+        0 => {
+            println!("[{}] renamed to [{}]", &name, &to);
+            set_outcome("success", Some(format!("renamed to [{}]", &to)));
+        }
+        _ => fail(format!(
+            "[{}] was only partly renamed to [{}], could not rename {}",
+            &name,
+            &to,
+            failures.join(", ")
+        )),
+    }
+
+    if was_watching {
+        watch_repo(
+            root,
+            work_dir,
+            cache_dir,
+            state_dir,
+            process_dir,
+            logs_dir,
+            config_dir_path,
+            Some(to),
+        );
+    }
+}
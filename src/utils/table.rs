@@ -3,12 +3,17 @@ use tabled::{
     settings::{
         Alignment, Border, Color, Padding, Panel, Style, Width,
         object::{Columns, Object, Rows, Segment},
+        peaker::PriorityMax,
         style::{BorderColor, HorizontalLine},
     },
 };
 use terminal_size::{Width as TermWidth, terminal_size};
 
-use super::structs::WatchStats;
+use std::time::Duration;
+
+use crate::core::utils::cache::format_duration;
+
+use super::structs::{HostOutcome, WatchStats};
 
 pub fn create_table<T>(data: &Vec<T>, title: &'static str) -> Table
 where
@@ -50,7 +55,7 @@ where
                 ),
             ]),
         )
-        .with(Width::wrap(t_width)) // don’t exceed
+        .with(Width::wrap(t_width).priority(PriorityMax::right())) // don’t exceed, widest columns give way first
         // .with(Width::wrap(term_width)) // don’t exceed
         // .with(Width::increase(term_width))
         .modify(
@@ -81,10 +86,91 @@ pub fn watch_status_table(data: Vec<WatchStats>, title: &'static str) -> Table {
 
         table.modify(
             // Columns::last().and(Rows::single(idx + 2)),
-            Rows::single(idx + 2).intersect(Columns::single(6)), // that one cell
+            Rows::single(idx + 2).intersect(Columns::single(8)), // that one cell
             color,
         );
     }
 
     return table;
 }
+
+pub fn display_option<T: std::fmt::Display>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "N/A".to_string(),
+    }
+}
+
+pub fn display_args(args: &[String]) -> String {
+    return args.join(" ");
+}
+
+pub fn display_time(time: &str) -> String {
+    match chrono::DateTime::parse_from_rfc3339(time) {
+        Ok(time) => {
+            return time
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string();
+        }
+        Err(_) => return time.to_string(),
+    }
+}
+
+pub fn display_cpu(cpu: &Option<f32>) -> String {
+    match cpu {
+        Some(cpu) => format!("{:.1}%", cpu),
+        None => "N/A".to_string(),
+    }
+}
+
+pub fn display_memory<T: Copy + Into<Option<u64>>>(bytes: &T) -> String {
+    match (*bytes).into() {
+        Some(bytes) => format!("{:.2}mb", bytes as f32 / 1_000_000_f32),
+        None => "N/A".to_string(),
+    }
+}
+
+pub fn display_age(secs: &u64) -> String {
+    return format_duration(Duration::from_secs(*secs));
+}
+
+pub fn display_secs(secs: &f64) -> String {
+    match *secs < 60.0 {
+        true => return format!("{:.1}s", secs),
+        false => return format_duration(Duration::from_secs_f64(*secs)),
+    }
+}
+
+pub fn display_hosts(hosts: &[HostOutcome]) -> String {
+    return hosts
+        .iter()
+        .map(|host| format!("{} {}", &host.host, &host.result))
+        .collect::<Vec<String>>()
+        .join(", ");
+}
+
+pub fn display_short_sha(sha: &str) -> String {
+    return sha.chars().take(12).collect();
+}
+
+pub fn display_version(version: &Option<String>) -> String {
+    match version {
+        Some(version) => display_short_sha(version),
+        None => "not deployed".to_string(),
+    }
+}
+
+pub fn display_watcher(pid: &Option<u32>) -> String {
+    match pid {
+        Some(pid) => format!("running ({})", pid),
+        None => "stopped".to_string(),
+    }
+}
+
+pub fn display_problems(problems: &[String]) -> String {
+    match problems.len() {
+        0 => "valid".to_string(),
+        n => format!("{} problem(s)", n),
+    }
+}
@@ -0,0 +1,591 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{IsTerminal, Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Sparkline, Table, TableState},
+};
+
+use crate::core::utils::{
+    cache::format_duration, filesystem::list_dir_contents, sampler::ProcessSampler,
+};
+
+use super::{
+    audit::{begin_audit, end_audit, set_outcome},
+    state::load_state,
+    structs::DeployRecord,
+    subcommands::{
+        has_entry_point, process_pids, spawn_flow, stop_all_track, stop_processes, watcher_pid,
+    },
+    table::display_memory,
+};
+
+const REFRESH: Duration = Duration::from_secs(1);
+/// Samples kept for the sparklines, one per refresh
+const HISTORY: usize = 120;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Log lines kept for the logs pane, and how far back a newly opened log is read
+const LOG_LINES: usize = 500;
+const LOG_TAIL_BYTES: u64 = 64 * 1024;
+
+/// What the dashboard knows about a configuration at the last refresh
+struct AppView {
+    name: String,
+    watcher_pid: Option<u32>,
+    /// Entry points that are still alive
+    pids: Vec<u32>,
+    cpu: Option<f32>,
+    memory: Option<u64>,
+    stage: Option<String>,
+    last_deploy: Option<DeployRecord>,
+    restarts: u64,
+}
+
+#[derive(Default)]
+struct History {
+    cpu: VecDeque<u64>,
+    memory: VecDeque<u64>,
+}
+
+/// End of the log shown in the logs pane, only what was appended since the last frame
+/// is read
+#[derive(Default)]
+struct LogTail {
+    path: String,
+    inode: u64,
+    offset: u64,
+    /// Set when reading starts mid-file, the first line read is cut and skipped
+    cut: bool,
+    partial: Vec<u8>,
+    lines: VecDeque<String>,
+}
+
+impl LogTail {
+    fn refresh(&mut self, path: &str) -> () {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => {
+                *self = LogTail::default();
+                return;
+            }
+        };
+        let meta = match file.metadata() {
+            Ok(meta) => meta,
+            Err(_) => return,
+        };
+
+        // another app, a rotated or a truncated log starts over from its last lines
+        if self.path != path || self.inode != meta.ino() || meta.len() < self.offset {
+            let offset = meta.len().saturating_sub(LOG_TAIL_BYTES);
+            *self = LogTail {
+                path: path.to_string(),
+                inode: meta.ino(),
+                offset,
+                cut: offset != 0,
+                ..Default::default()
+            };
+        }
+
+        if meta.len() == self.offset || file.seek(SeekFrom::Start(self.offset)).is_err() {
+            return;
+        }
+
+        let mut bytes = Vec::new();
+        if file.read_to_end(&mut bytes).is_err() {
+            return;
+        }
+        self.offset += bytes.len() as u64;
+        self.partial.extend_from_slice(&bytes);
+
+        let end = match self.partial.iter().rposition(|byte| *byte == b'\n') {
+            Some(end) => end,
+            None => return,
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        let text = String::from_utf8_lossy(&complete).to_string();
+
+        let mut lines = text.lines();
+        if self.cut {
+            lines.next();
+            self.cut = false;
+        }
+        for line in lines {
+            if self.lines.len() == LOG_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line.to_string());
+        }
+    }
+}
+
+struct Dashboard {
+    root: Option<String>,
+    state_dir: String,
+    process_dir: String,
+    logs_dir: String,
+    config_dir_path: String,
+    sampler: ProcessSampler,
+    apps: Vec<AppView>,
+    history: HashMap<String, History>,
+    table: TableState,
+    show_logs: bool,
+    logs: LogTail,
+    message: String,
+}
+
+fn push_sample(samples: &mut VecDeque<u64>, value: u64) -> () {
+    if samples.len() == HISTORY {
+        samples.pop_front();
+    }
+    samples.push_back(value);
+}
+
+/// The last `width` samples drawn with block characters, scaled to their own maximum
+fn spark_text(samples: &VecDeque<u64>, width: usize) -> String {
+    let start = samples.len().saturating_sub(width);
+    let max = samples
+        .iter()
+        .skip(start)
+        .max()
+        .copied()
+        .unwrap_or(0)
+        .max(1);
+
+    return samples
+        .iter()
+        .skip(start)
+        .map(|value| SPARKS[((*value * 7) / max) as usize])
+        .collect();
+}
+
+fn deploy_summary(deploy: &Option<DeployRecord>) -> String {
+    let deploy = match deploy {
+        Some(deploy) => deploy,
+        None => return "never".to_string(),
+    };
+
+    let age = chrono::DateTime::parse_from_rfc3339(&deploy.finished_at)
+        .ok()
+        .and_then(|finished| {
+            (chrono::Local::now().fixed_offset() - finished)
+                .to_std()
+                .ok()
+        })
+        .map(|age| format!(" {} ago", format_duration(age)))
+        .unwrap_or_default();
+
+    return format!(
+        "{} {}{}",
+        deploy.sha.chars().take(8).collect::<String>(),
+        &deploy.result,
+        age
+    );
+}
+
+impl Dashboard {
+    fn refresh(&mut self) -> () {
+        let mut names: Vec<String> = list_dir_contents(&self.config_dir_path)
+            .unwrap_or_default()
+            .iter()
+            .map(|file| file.split(".").next().unwrap_or("").to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let all_pids: Vec<u32> = names
+            .iter()
+            .flat_map(|name| process_pids(&self.process_dir, name))
+            .collect();
+
+        // cpu usage is measured between two refreshes of the same sampler
+        self.sampler.refresh(&all_pids);
+
+        let mut apps = Vec::<AppView>::new();
+
+        for name in names {
+            let state = load_state(&self.state_dir, &name);
+            let pids: Vec<u32> = process_pids(&self.process_dir, &name)
+                .into_iter()
+                .filter(|pid| self.sampler.usage(&[*pid]).is_some())
+                .collect();
+
+            // the entry points along with their children
+            let usage = self.sampler.usage(&pids);
+            let cpu = usage.as_ref().map(|usage| usage.cpu_percent);
+            let memory = usage.as_ref().map(|usage| usage.memory_bytes);
+
+            let history = self.history.entry(name.clone()).or_default();
+            push_sample(&mut history.cpu, (cpu.unwrap_or(0.0) * 10.0) as u64);
+            push_sample(&mut history.memory, memory.unwrap_or(0));
+
+            apps.push(AppView {
+                watcher_pid: watcher_pid(&self.process_dir, &name).and_then(|pid| pid.parse().ok()),
+                name,
+                pids,
+                cpu,
+                memory,
+                stage: state.stage,
+                last_deploy: state.last_deploy,
+                restarts: state.restarts,
+            });
+        }
+
+        self.apps = apps;
+
+        match self.apps.len() {
+            0 => self.table.select(None),
+            len => self
+                .table
+                .select(Some(self.table.selected().unwrap_or(0).min(len - 1))),
+        }
+    }
+
+    fn selected(&self) -> Option<&AppView> {
+        return self.table.selected().and_then(|idx| self.apps.get(idx));
+    }
+
+    fn deploy(&mut self, name: &str, watching: bool) -> () {
+        let log_file = format!("{}/{}.watch.log", &self.logs_dir, name);
+
+        // a running watcher picks the request up, otherwise deploy right away
+        let res = match watching {
+            true => spawn_flow(&self.root, &["deploy", "-n", name, "--daemon"], None, None),
+            false => spawn_flow(&self.root, &["deploy", "-n", name], Some(&log_file), None),
+        };
+
+        self.message = match res {
+            Ok(_) if watching => format!("deploy of [{}] handed to the watcher", name),
+            Ok(_) => format!("deploying [{}], see its logs with l", name),
+            Err(err) => format!("could not deploy [{}] : {}", name, err),
+        };
+    }
+
+    fn restart(&mut self, name: &str) -> () {
+        // flow start asks for missing entry points, which can't be answered from here
+        if !has_entry_point(&self.config_dir_path, name) {
+            self.message = format!(
+                "[{}] has no entry point yet, set it up once with flow start -n {}",
+                name, name
+            );
+            return;
+        }
+
+        stop_processes(&self.process_dir, name);
+
+        self.message = match spawn_flow(&self.root, &["start", "-n", name], None, None) {
+            Ok(_) => format!("restarting [{}]", name),
+            Err(err) => format!("could not restart [{}] : {}", name, err),
+        };
+    }
+
+    fn stop(&mut self, name: &str) -> () {
+        begin_audit(&self.logs_dir, "stop", Some(name.to_string()), None);
+        let stopped = stop_processes(&self.process_dir, name)
+            .into_iter()
+            .filter(|(_, stopped)| *stopped)
+            .count();
+
+        self.message = match stopped {
+            0 => {
+                set_outcome("unchanged", None);
+                format!("[{}] has no running process", name)
+            }
+            n => format!("stopped {} process(es) of [{}]", n, name),
+        };
+        end_audit();
+    }
+
+    fn toggle_watch(&mut self, name: &str, watching: bool) -> () {
+        if watching {
+            begin_audit(&self.logs_dir, "stop", Some(name.to_string()), None);
+            stop_all_track(&self.process_dir, Some(name.to_string()), true);
+            end_audit();
+            self.message = format!("stopped watching [{}]", name);
+            return;
+        }
+
+        self.message = match spawn_flow(&self.root, &["watch", "-n", name], None, None) {
+            Ok(_) => format!("watching [{}]", name),
+            Err(err) => format!("could not watch [{}] : {}", name, err),
+        };
+    }
+
+    /// Handles a key press, telling whether the dashboard should close
+    fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let selected = self
+            .selected()
+            .map(|app| (app.name.clone(), app.watcher_pid.is_some()));
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Enter | KeyCode::Char('l') => self.show_logs = !self.show_logs,
+            KeyCode::Char('d') => {
+                if let Some((name, watching)) = selected {
+                    self.deploy(&name, watching);
+                }
+            }
+            KeyCode::Char('r') => {
+                if let Some((name, _)) = selected {
+                    self.restart(&name);
+                }
+            }
+            KeyCode::Char('s') => {
+                if let Some((name, _)) = selected {
+                    self.stop(&name);
+                }
+            }
+            KeyCode::Char('w') => {
+                if let Some((name, watching)) = selected {
+                    self.toggle_watch(&name, watching);
+                }
+            }
+            _ => {}
+        }
+
+        return false;
+    }
+
+    fn draw_table(&mut self, frame: &mut Frame, area: Rect) -> () {
+        let header = Row::new([
+            "name",
+            "watcher",
+            "app",
+            "stage",
+            "cpu",
+            "",
+            "memory",
+            "",
+            "last deploy",
+            "restarts",
+        ])
+        .style(Style::new().fg(Color::Cyan));
+
+        let rows: Vec<Row> = self
+            .apps
+            .iter()
+            .map(|app| {
+                let history = &self.history[&app.name];
+                let watcher = match app.watcher_pid {
+                    Some(pid) => format!("watched ({})", pid),
+                    None => "unwatched".to_string(),
+                };
+                let running = match app.pids.len() {
+                    0 => "stopped".to_string(),
+                    n => format!("{} running", n),
+                };
+
+                Row::new([
+                    app.name.clone(),
+                    watcher,
+                    running,
+                    app.stage.clone().unwrap_or("idle".to_string()),
+                    match app.cpu {
+                        Some(cpu) => format!("{:.1}%", cpu),
+                        None => "N/A".to_string(),
+                    },
+                    spark_text(&history.cpu, 16),
+                    display_memory(&app.memory),
+                    spark_text(&history.memory, 16),
+                    deploy_summary(&app.last_deploy),
+                    app.restarts.to_string(),
+                ])
+                .style(match (app.stage.is_some(), app.watcher_pid.is_some()) {
+                    (true, _) => Style::new().fg(Color::Yellow),
+                    (false, true) => Style::new().fg(Color::LightGreen),
+                    (false, false) => Style::new(),
+                })
+            })
+            .collect();
+
+        let widths = [
+            Constraint::Fill(1),
+            Constraint::Length(18),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(7),
+            Constraint::Length(16),
+            Constraint::Length(10),
+            Constraint::Length(16),
+            Constraint::Length(26),
+            Constraint::Length(8),
+        ];
+
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(" Fast⚡Flow "))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn draw_sparklines(&self, frame: &mut Frame, area: Rect) -> () {
+        let app = match self.selected() {
+            Some(app) => app,
+            None => return,
+        };
+        let history = &self.history[&app.name];
+        let [cpu_area, memory_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(area);
+
+        // the newest samples sit on the right edge
+        let tail = |samples: &VecDeque<u64>, width: u16| -> Vec<u64> {
+            let width = width.saturating_sub(2) as usize;
+            samples
+                .iter()
+                .skip(samples.len().saturating_sub(width))
+                .copied()
+                .collect()
+        };
+
+        let cpu = tail(&history.cpu, cpu_area.width);
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(format!(" {} cpu ", &app.name)))
+                .data(&cpu)
+                .style(Style::new().fg(Color::LightGreen)),
+            cpu_area,
+        );
+
+        let memory = tail(&history.memory, memory_area.width);
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(format!(" {} memory ", &app.name)))
+                .data(&memory)
+                .style(Style::new().fg(Color::LightBlue)),
+            memory_area,
+        );
+    }
+
+    fn draw_logs(&mut self, frame: &mut Frame, area: Rect) -> () {
+        let name = match self.selected() {
+            Some(app) => app.name.clone(),
+            None => return,
+        };
+
+        self.logs
+            .refresh(&format!("{}/{}.watch.log", &self.logs_dir, &name));
+        let height = area.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .logs
+            .lines
+            .iter()
+            .skip(self.logs.lines.len().saturating_sub(height))
+            .map(|line| Line::raw(line.clone()))
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(format!(" {} logs ", &name))),
+            area,
+        );
+    }
+
+    fn draw(&mut self, frame: &mut Frame) -> () {
+        let table_height = self.apps.len() as u16 + 3;
+
+        let [table_area, spark_area, logs_area, footer_area] = Layout::vertical([
+            match self.show_logs {
+                true => Constraint::Length(table_height),
+                false => Constraint::Min(table_height),
+            },
+            Constraint::Length(7),
+            match self.show_logs {
+                true => Constraint::Min(5),
+                false => Constraint::Length(0),
+            },
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_table(frame, table_area);
+        self.draw_sparklines(frame, spark_area);
+        if self.show_logs {
+            self.draw_logs(frame, logs_area);
+        }
+
+        let help = "↑/↓ select  l logs  d deploy  r restart  s stop  w watch/unwatch  q quit";
+        let footer = match self.message.len() {
+            0 => help.to_string(),
+            _ => format!("{}  │  {}", &self.message, help),
+        };
+        frame.render_widget(
+            Paragraph::new(footer).style(Style::new().fg(Color::DarkGray)),
+            footer_area,
+        );
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), String> {
+        let mut last_refresh = Instant::now();
+        self.refresh();
+
+        loop {
+            terminal
+                .draw(|frame| self.draw(frame))
+                .map_err(|err| err.to_string())?;
+
+            let timeout = REFRESH.saturating_sub(last_refresh.elapsed());
+            if event::poll(timeout).map_err(|err| err.to_string())?
+                && let Event::Key(key) = event::read().map_err(|err| err.to_string())?
+                && key.kind == KeyEventKind::Press
+                && self.on_key(key.code, key.modifiers)
+            {
+                return Ok(());
+            }
+
+            if last_refresh.elapsed() >= REFRESH {
+                self.refresh();
+                last_refresh = Instant::now();
+            }
+        }
+    }
+}
+
+/// Full screen status of every configuration refreshed in place, with keybindings to act
+/// on the selected one
+pub fn top(
+    root: Option<String>,
+    state_dir: &str,
+    process_dir: &str,
+    logs_dir: &str,
+    config_dir_path: &str,
+) -> () {
+    if !std::io::stdout().is_terminal() {
+        println!("flow top needs a terminal, use flow status for a one-shot table");
+        return;
+    }
+
+    let mut dashboard = Dashboard {
+        root,
+        state_dir: state_dir.to_string(),
+        process_dir: process_dir.to_string(),
+        logs_dir: logs_dir.to_string(),
+        config_dir_path: config_dir_path.to_string(),
+        sampler: ProcessSampler::default(),
+        apps: Vec::new(),
+        history: HashMap::new(),
+        table: TableState::default(),
+        show_logs: false,
+        logs: LogTail::default(),
+        message: String::new(),
+    };
+
+    let mut terminal = ratatui::init();
+    let res = dashboard.run(&mut terminal);
+    ratatui::restore();
+
+    if let Err(err) = res {
+        println!("{}", err);
+    }
+}
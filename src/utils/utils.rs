@@ -1,104 +1,144 @@
-use log::{error, info};
-use serde::de::DeserializeOwned;
+use log::{error, info, warn};
 use std::{
     collections::HashMap,
-    fs::{self, File, OpenOptions, create_dir_all, read_to_string},
-    io::{self, Read, Write},
-    os::unix::ffi::OsStrExt,
+    fs,
     path::Path,
     process::Command,
+    time::{Duration, Instant},
 };
-use sysinfo::System;
 
-use crate::utils::structs::ConfigFile;
-
-use super::structs::{FromTo, SysInfo};
-
-pub fn prompt_user(str: &str) -> Result<String, String> {
-    // Prompt the user for input
-    println!("{} : ", str);
-
-    let mut input = String::new();
+use crate::{
+    core::utils::{
+        cache::{cache_key, has_artifacts, prune_entries, restore_artifacts, store_artifacts},
+        command::{execute_commande, prompt_user},
+        filesystem::{
+            check_dir_exist_or_create, is_directory, load_file_parsed, read_from_file_ut,
+            write_to_file_ut,
+        },
+        git::extract_repo_info,
+        permissions::{
+            Ownership, apply_file_ownership, apply_ownership, resolve_ids, resolve_ownership,
+        },
+        remote::{parse_target, push_to_remote, shell_quote},
+        rotate::{rotate, rotation_due},
+        sync::{SyncAction, apply_sync, plan_sync, resolve_sources},
+        template::render_template,
+    },
+    utils::{
+        logger::set_log_run,
+        notify::{Notification, notifiers_for, notify},
+        stages::{StageContext, fail_run, run_stage},
+        state::{record_build, record_deploy, record_poll_error, record_stage},
+        structs::{ConfigFile, DeployRequest, NotifyEvent},
+        validate::validate_config,
+    },
+};
 
-    match io::stdin().read_line(&mut input) {
-        Ok(_ret) => (),
-        Err(err) => {
-            return Err(format!("{}", err));
+use super::structs::{FromTo, HostOutcome, LogsConfig, TemplateConfig};
+
+pub const DEFAULT_CACHE_MAX_SIZE_MB: u64 = 1024;
+pub const DEFAULT_CACHE_MAX_AGE_DAYS: u64 = 30;
+pub const DEFAULT_LOG_MAX_SIZE_MB: u64 = 10;
+pub const DEFAULT_LOG_KEEP: usize = 5;
+pub const DEFAULT_NOTIFY_RETRIES: u32 = 2;
+pub const DEFAULT_NOTIFY_RETRY_DELAY: u64 = 2;
+pub const DEFAULT_HISTORY_SIZE: usize = 50;
+pub const DEFAULT_STOP_GRACE: u64 = 5;
+
+pub fn watch_config_repo(
+    work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
+    config_file_path: &str,
+) -> () {
+    let request_path = deploy_request_path(work_dir, config_file_path);
+
+    // a deploy asked with `flow deploy --daemon` replaces this poll
+    let request = match load_file_parsed::<DeployRequest>(&request_path) {
+        Ok(request) => {
+            let _ = fs::remove_file(&request_path);
+            info!(
+                "Deploy requested for {}{}",
+                request
+                    .git_ref
+                    .clone()
+                    .unwrap_or("the branch head".to_string()),
+                if request.force { ", forced" } else { "" }
+            );
+            request
         }
-    }
+        Err(_) => DeployRequest {
+            poll: true,
+            ..DeployRequest::default()
+        },
+    };
 
-    let input = String::from(input.trim().to_lowercase());
-    return Ok(input);
+    deploy_config_repo(work_dir, cache_dir, state_dir, config_file_path, &request);
 }
 
-pub fn execute_commande(commande: &str) -> Result<String, String> {
-    match Command::new("sh").arg("-c").arg(commande).output() {
-        Ok(output) => {
-            if !output.status.success() {
-                // let y = String::
-                return Err(String::from_utf8_lossy(&output.stderr).to_string());
-            }
-            Ok(String::from_utf8_lossy(&output.stdout).to_string())
-        }
-        Err(err) => Err(err.to_string()),
-    }
+/// Name of the configuration stored at `config_file_path`
+pub fn config_name(config_file_path: &str) -> String {
+    return Path::new(config_file_path)
+        .file_name()
+        .map(|name| {
+            name.to_string_lossy()
+                .split('.')
+                .next()
+                .unwrap_or("")
+                .to_string()
+        })
+        .unwrap_or_default();
 }
 
-pub fn extract_repo_info(url: &str) -> Option<(&str, &str)> {
-    let parts: Vec<&str> = url.split('/').collect();
-
-    // We need at least username and repo parts
-    if parts.len() < 2 {
-        return None;
-    }
-
-    // Get the last part (repo) and strip .git suffix
-    let repo = parts.last()?.strip_suffix(".git")?;
-
-    // The username should be second-to-last for standard GitHub URLs
-    // Handle cases like "https://github.com/owner/repo.git"
-    let username = parts.get(parts.len() - 2)?;
+/// Where `flow deploy --daemon` leaves its request for the watcher of `config_file_path`
+pub fn deploy_request_path(work_dir: &str, config_file_path: &str) -> String {
+    return format!("{}/{}.deploy.json", work_dir, config_name(config_file_path));
+}
 
-    Some((username, repo))
+/// Where a ref deployed by hand records the branch head it holds the polls at,
+/// the config's cache dir is shared by the watcher and `flow deploy`
+fn hold_path(cache_dir: &str) -> String {
+    return format!("{}/held_head", cache_dir);
 }
 
-pub fn get_sys_info(pid_str: &str) -> Result<SysInfo, bool> {
-    let pid: usize = match pid_str.parse() {
-        Ok(num) => num,
-        Err(err) => {
-            println!("error : {}", err);
-            return Err(false);
-        }
+/// Holds the polls while the branch head is `head`, None lifts the hold
+fn record_hold(cache_dir: &str, head: Option<String>) -> () {
+    let path = hold_path(cache_dir);
+    let _ = match head {
+        Some(head) => fs::create_dir_all(cache_dir).and_then(|_| fs::write(&path, head)),
+        None => fs::remove_file(&path),
     };
-    let mut system = System::new_all();
-    system.refresh_all();
-
-    match system.process(pid.into()) {
-        Some(process) => {
-            let mem: f32 = process.memory() as f32 / 1_000_000 as f32;
-            return Ok(SysInfo {
-                name: format!("{:?}", process.name()),
-                cpu_usage: format!("{}%", process.cpu_usage()),
-                memory: format!("{:.2}mb", mem),
-                status: format!("{}", process.status()),
-            });
-        }
-        None => Err(false),
-    }
-
-    // println!("Name: {}", process.name());
-    // println!("CPU usage: {}%", process.cpu_usage());
-    // println!("Memory: {} KB", process.memory());
-    // println!("Status: {:?}", process.status());
 }
 
-pub fn watch_config_repo(work_dir: &str, config_file_path: &str) -> () {
+pub fn deploy_config_repo(
+    work_dir: &str,
+    cache_dir: &str,
+    state_dir: &str,
+    config_file_path: &str,
+    request: &DeployRequest,
+) -> () {
+    // a poll only belongs to a run once it finds something to deploy
+    set_log_run(None);
     info!("Reading config");
 
-    let mut config = match load_file_parsed::<ConfigFile>(&config_file_path) {
-        Ok(conf) => conf,
+    let content = match fs::read_to_string(config_file_path) {
+        Ok(content) => content,
         Err(err) => {
-            error!("{}", err);
+            error!("{} : {}", config_file_path, err);
+            record_poll_error(state_dir, &config_name(config_file_path));
+            return;
+        }
+    };
+
+    // an invalid config is refused as a whole rather than deployed partly
+    let mut config = match validate_config(&content, false) {
+        Ok(conf) => conf,
+        Err(diagnostics) => {
+            error!("Invalid config {}", config_file_path);
+            for diagnostic in diagnostics {
+                error!("    {}", diagnostic);
+            }
+            record_poll_error(state_dir, &config_name(config_file_path));
             return;
         }
     };
@@ -109,13 +149,26 @@ pub fn watch_config_repo(work_dir: &str, config_file_path: &str) -> () {
         mouve,
         version,
         branch,
+        pre_build,
+        pre_deploy,
+        post_deploy,
+        on_failure,
+        max_parallel,
+        cache,
+        logs: _,
+        notify: config_notify,
+        env: config_env,
+        vars: _,
+        secrets_file: _,
+        templates,
+        hosts,
         entry_point: _,
     } = config.clone();
 
-    let curr_version = match version {
-        Some(v) => v,
-        None => String::new(),
-    };
+    let on_failure = on_failure.unwrap_or_default();
+    let max_parallel = max_parallel.unwrap_or(default_max_parallel());
+
+    let curr_version = version.unwrap_or_default();
 
     let actual_branch = match branch {
         Some(b) => b,
@@ -125,16 +178,20 @@ pub fn watch_config_repo(work_dir: &str, config_file_path: &str) -> () {
     // Check if workdir exist else create
     check_dir_exist_or_create(&format!("{}/exmaple", &work_dir));
 
-    info!(
-        "Fetching the most recent version for branch {}",
-        &actual_branch
-    );
+    match &request.git_ref {
+        Some(git_ref) => info!("Resolving {}", git_ref),
+        None => info!(
+            "Fetching the most recent version for branch {}",
+            &actual_branch
+        ),
+    }
 
     // Extract the user name and repo
     let repo_info = match extract_repo_info(&repo) {
         Some(rep) => rep,
         None => {
             error!("error while parsing your github repo to extract the name, check it");
+            record_poll_error(state_dir, &config_name(config_file_path));
             let _ = execute_commande(&format!("cd {} && rm -rf {}", &work_dir, &repo));
             return;
         }
@@ -146,87 +203,350 @@ pub fn watch_config_repo(work_dir: &str, config_file_path: &str) -> () {
     // remove fetched in case it exist
     let _ = execute_commande(&format!("rm -rf {}/{}", &work_dir, &folder_name));
 
-    // Fetch the current repo version
-    let fetch_version = match execute_commande(&format!(
-        "git ls-remote git@github.com:{}/{}.git {:?}",
-        &username, &folder_name, &actual_branch
-    )) {
-        Ok(v) => v.trim().split("refs").next().unwrap().to_string(),
+    // Fetch the current repo version, or the one asked for
+    let fetch_version = match &request.git_ref {
+        Some(git_ref) => resolve_ref(username, folder_name, git_ref),
+        None => fetch_remote_version(username, folder_name, &actual_branch),
+    };
+    let fetch_version = match fetch_version {
+        Ok(v) => v,
         Err(err) => {
             error!("{}", err);
+            record_poll_error(state_dir, &config_name(config_file_path));
             return;
         }
     };
 
+    // a ref deployed by hand, such as an older commit, stays until the branch moves on
+    if request.poll {
+        let held = read_from_file_ut(&hold_path(cache_dir)).unwrap_or_default();
+        if held.trim() == fetch_version {
+            return;
+        }
+    }
+
     // If the current version is the newest do nothing
-    if curr_version == fetch_version {
+    // a short sha given with --ref matches the full one recorded
+    let up_to_date = !fetch_version.is_empty() && curr_version.trim().starts_with(&fetch_version);
+    if up_to_date && !request.force {
         info!("Up to date with branch");
         return;
     }
 
-    // refreshing the controle version
-    config.version = Some(fetch_version);
-    config.branch = Some(actual_branch);
+    let repo_dir = format!("{}/{}", &work_dir, &folder_name);
 
     // Clone repository in local
-    match execute_commande(&format!(
-        "cd {work_dir} && git clone git@github.com:{}/{}.git",
-        &username, &folder_name
-    )) {
-        Ok(_) => info!("cloned repository {repo}"),
+    let fetch_version = match clone_at(work_dir, username, folder_name, &fetch_version) {
+        Ok(sha) => {
+            info!("cloned repository {repo} at {}", &sha);
+            sha
+        }
         Err(err) => {
             error!("{}", err);
+            record_poll_error(state_dir, &config_name(config_file_path));
+            let _ = execute_commande(&format!("rm -rf {}", &repo_dir));
             return;
         }
+    };
+
+    // refreshing the controle version
+    config.version = Some(fetch_version.clone());
+    config.branch = Some(actual_branch.clone());
+    let started_at = chrono::Local::now();
+    let run_id = started_at.format("%Y%m%d-%H%M%S").to_string();
+
+    set_log_run(Some(&run_id));
+    info!("Starting run {}", &run_id);
+
+    // deploy metadata exposed to every step and hook
+    let metadata = deploy_metadata(&fetch_version, &actual_branch, &repo_dir, &mouve, &run_id);
+
+    let mut env = config_env.unwrap_or_default();
+    env.extend(metadata.clone());
+
+    let hosts = hosts.unwrap_or_default();
+
+    let mut ctx = StageContext {
+        repo_dir: repo_dir.clone(),
+        env,
+        max_parallel,
+        hosts: hosts.clone(),
+        on_failure,
+        name: config_name(config_file_path),
+        state_dir: state_dir.to_string(),
+        started_at,
+        notify: notifiers_for(config_notify),
+        host_outcomes: Vec::new(),
+    };
+
+    notify(
+        &ctx.notify,
+        Notification::for_run(NotifyEvent::DeployStarted, &ctx, None),
+    );
+
+    let cache = cache.unwrap_or_default();
+    let cache_enabled = cache.enabled.unwrap_or(true);
+    let artifacts_key = artifacts_cache_key(&config, &fetch_version);
+
+    let mut cache_hit = false;
+
+    if cache_enabled && has_artifacts(cache_dir, &artifacts_key) {
+        match restore_artifacts(cache_dir, &artifacts_key, &repo_dir) {
+            Ok(_) => {
+                info!(
+                    "Restored cached artifacts {}, skipping the build",
+                    &artifacts_key
+                );
+                cache_hit = true;
+            }
+            Err(err) => warn!(
+                "Failed to restore cached artifacts {} : {}",
+                &artifacts_key, err
+            ),
+        }
     }
 
-    // Executing build
+    if !cache_hit {
+        let build_started = Instant::now();
+        for (stage, steps) in [
+            ("pre_build", pre_build.unwrap_or_default()),
+            ("build", build),
+        ] {
+            if let Err(err) = run_stage(stage, &steps, &ctx) {
+                fail_run(stage, &err, &ctx);
+                return;
+            }
+        }
+        record_build(&ctx, build_started.elapsed().as_secs_f64());
+
+        if cache_enabled {
+            let mut artifacts = Vec::<String>::new();
+            for entry in &mouve {
+                for src in resolve_sources(&repo_dir, entry)
+                    .unwrap_or_default()
+                    .values()
+                {
+                    if let Ok(rel) = src.strip_prefix(&repo_dir) {
+                        artifacts.push(rel.to_string_lossy().to_string());
+                    }
+                }
+            }
 
-    info!("Starting Building Process");
+            match store_artifacts(cache_dir, &artifacts_key, &repo_dir, &artifacts) {
+                Ok(archive) => info!("Cached build artifacts at {}", archive),
+                Err(err) => warn!("Failed to cache build artifacts : {}", err),
+            }
 
-    for command in build {
-        match execute_commande(&format!(
-            "cd {}/{} && {}",
-            &work_dir, &folder_name, &command
-        )) {
-            Ok(val) => {
-                info!("{}", val);
-                info!("{command} : commande success ")
+            match prune_entries(
+                cache_dir,
+                Some(Duration::from_secs(
+                    cache.max_age_days.unwrap_or(DEFAULT_CACHE_MAX_AGE_DAYS) * 24 * 3600,
+                )),
+                Some(cache.max_size_mb.unwrap_or(DEFAULT_CACHE_MAX_SIZE_MB) * 1_000_000),
+            ) {
+                Ok(removed) => {
+                    for entry in removed {
+                        info!("Evicted cached artifacts {}", entry.key);
+                    }
+                }
+                Err(err) => warn!("Failed to prune the artifact cache : {}", err),
             }
+        }
+    }
+
+    if let Err(err) = run_stage("pre_deploy", &pre_deploy.unwrap_or_default(), &ctx) {
+        fail_run("pre_deploy", &err, &ctx);
+        return;
+    }
+
+    // Executing move
+
+    // render every template before moving anything so a missing variable aborts the deploy
+    let rendered = match template_vars(&config, &metadata)
+        .and_then(|vars| render_templates(&templates.unwrap_or_default(), &repo_dir, &vars))
+    {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            fail_run("templates", &err, &ctx);
+            return;
+        }
+    };
+
+    info!(stage = "mouve"; "Starting Moving Process");
+    record_stage(&ctx, "mouve");
+
+    // resolve every local owner first so a missing user fails before anything is copied,
+    // remote owners are resolved by rsync on the remote side
+    let mut ownerships = Vec::<Option<Ownership>>::new();
+    for command in &mouve {
+        if parse_target(&command.to, &hosts).is_some() {
+            ownerships.push(None);
+            continue;
+        }
+        match resolve_ownership(command) {
+            Ok(ownership) => ownerships.push(Some(ownership)),
             Err(err) => {
-                let _ = execute_commande(&format!("cd {} && rm -rf {}", &work_dir, &folder_name));
-                error!("{}", err);
+                fail_run("mouve", &err, &ctx);
                 return;
             }
         }
     }
-    // Executing move
 
-    info!("Starting Moving Process");
-    for command in mouve {
+    // every host is attempted so the log shows the outcome on each of them
+    let mut host_results = Vec::<(String, Option<String>)>::new();
+
+    for (idx, (command, ownership)) in mouve.iter().zip(ownerships).enumerate() {
+        if let Some(target) = parse_target(&command.to, &hosts) {
+            let stage_dir = format!("{}.flow-stage-{}", &repo_dir, idx);
+
+            match push_to_remote(&repo_dir, &stage_dir, command, &target, false) {
+                Ok((plan, _)) => {
+                    info!(
+                        stage = "mouve",
+                        host = target.name.as_str();
+                        "moving {} to {} : commande success, {} files",
+                        &command.from,
+                        &target.path,
+                        plan.items.len()
+                    );
+                    host_results.push((target.name, None));
+                }
+                Err(err) => {
+                    error!(
+                        stage = "mouve",
+                        host = target.name.as_str();
+                        "moving {} to {} : {}",
+                        &command.from,
+                        &target.path,
+                        err
+                    );
+                    host_results.push((target.name, Some(err)));
+                }
+            }
+            continue;
+        }
+
         check_dir_exist_or_create(&format!("{}/example", &command.to));
 
-        match execute_commande(&format!(
-            "cd {}/{} && cp -r {} {}",
-            &work_dir, &folder_name, &command.from, &command.to
-        )) {
-            Ok(_) => info!("moving {} : commande success ", &command.from),
+        let res = plan_sync(&repo_dir, command).and_then(|plan| {
+            apply_sync(&plan)?;
+            apply_ownership(&plan, &ownership.unwrap_or_default())?;
+            Ok(plan)
+        });
+
+        match res {
+            Ok(plan) => info!(
+                stage = "mouve";
+                "moving {} : commande success, {} created, {} overwritten, {} unchanged, {} deleted",
+                &command.from,
+                plan.count(SyncAction::Create),
+                plan.count(SyncAction::Overwrite),
+                plan.count(SyncAction::Unchanged),
+                plan.count(SyncAction::Delete)
+            ),
+            // the destination is left as the sync got it, never wiped
+            Err(err) => {
+                fail_run("mouve", &err, &ctx);
+                return;
+            }
+        }
+    }
+
+    if !host_results.is_empty() {
+        let mut failed_hosts = Vec::<String>::new();
+        let mut names: Vec<&String> = host_results.iter().map(|(name, _)| name).collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            // the first failing entry of a host is the one its outcome reports
+            let error = host_results
+                .iter()
+                .filter(|(host, _)| host == name)
+                .find_map(|(_, err)| err.clone());
+            match &error {
+                None => info!(stage = "mouve", host = name.as_str(); "deploy succeeded"),
+                Some(_) => {
+                    error!(stage = "mouve", host = name.as_str(); "deploy failed");
+                    failed_hosts.push(name.clone());
+                }
+            }
+            ctx.host_outcomes.push(HostOutcome {
+                host: name.clone(),
+                result: match error {
+                    Some(_) => "failed".to_string(),
+                    None => "success".to_string(),
+                },
+                error,
+            });
+        }
+
+        if !failed_hosts.is_empty() {
+            let err = format!("deploy failed on {}", failed_hosts.join(", "));
+            fail_run("mouve", &err, &ctx);
+            return;
+        }
+    }
+
+    for (template, content, ownership) in rendered {
+        check_dir_exist_or_create(&template.to);
+
+        // write beside the destination and swap it in so readers never see half a file
+        let tmp_path = format!("{}.flow-tmp", &template.to);
+        let res = fs::write(&tmp_path, content)
+            .map_err(|err| err.to_string())
+            .and_then(|_| apply_file_ownership(Path::new(&tmp_path), &ownership))
+            .and_then(|_| fs::rename(&tmp_path, &template.to).map_err(|err| err.to_string()));
+
+        match res {
+            Ok(_) => info!(
+                stage = "templates";
+                "rendered {} to {}",
+                &template.from,
+                &template.to
+            ),
             Err(err) => {
-                let _ = execute_commande(&format!("cd {} && rm -rf {}", &work_dir, &folder_name));
-                let _ = execute_commande(&format!("rm -rf {}", &command.to,));
-                error!("{}", err);
+                let _ = fs::remove_file(&tmp_path);
+                let err = format!("{} : {}", &template.to, err);
+                fail_run("templates", &err, &ctx);
                 return;
             }
         }
     }
-    let _ = execute_commande(&format!("cd {} && rm -rf {}", &work_dir, &folder_name));
+
+    // polls would redeploy the branch head right away, hold them while it stays the same
+    let held_head = match &request.git_ref {
+        Some(_) => fetch_remote_version(username, folder_name, &actual_branch).ok(),
+        None => None,
+    };
+    if let Some(head) = &held_head {
+        info!(
+            "Holding {} until {} moves on from {}",
+            &fetch_version, &actual_branch, head
+        );
+    }
+    record_hold(cache_dir, held_head);
+
+    // the files are already deployed, so a failing hook doesn't cancel the new version
+    match run_stage("post_deploy", &post_deploy.unwrap_or_default(), &ctx) {
+        Ok(_) => {
+            record_deploy(&ctx, None);
+            notify(
+                &ctx.notify,
+                Notification::for_run(NotifyEvent::DeploySucceeded, &ctx, None),
+            );
+        }
+        Err(err) => fail_run("post_deploy", &err, &ctx),
+    }
+
+    let _ = execute_commande(&format!("rm -rf {}", &repo_dir));
 
     info!("repository tracked");
 
     match execute_commande(&format!("rm {}", &config_file_path)) {
         Ok(_) => {
             let _ = write_to_file_ut(
-                &config_file_path,
+                config_file_path,
                 &serde_json::to_string_pretty::<ConfigFile>(&config).unwrap(),
             );
         }
@@ -239,9 +559,192 @@ pub fn watch_config_repo(work_dir: &str, config_file_path: &str) -> () {
     return ();
 }
 
-pub fn check_existing_runner(extention: &str) -> Result<&str, String> {
-    use std::collections::HashMap;
+/// Rotates `log_path` when `logs` asks for it, telling whether it did. Logs held open by
+/// other processes are copied and truncated rather than moved
+pub fn rotate_log(log_path: &str, logs: &LogsConfig, copy_truncate: bool) -> Result<bool, String> {
+    let max_size = logs.max_size_mb.unwrap_or(DEFAULT_LOG_MAX_SIZE_MB) * 1_000_000;
+    let max_age = logs
+        .max_age_days
+        .map(|days| Duration::from_secs(days * 24 * 3600));
+
+    if !rotation_due(log_path, Some(max_size), max_age) {
+        return Ok(false);
+    }
+
+    rotate(
+        log_path,
+        logs.keep.unwrap_or(DEFAULT_LOG_KEEP),
+        logs.compress.unwrap_or(false),
+        copy_truncate,
+    )?;
+
+    return Ok(true);
+}
+
+pub fn default_max_parallel() -> usize {
+    return std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+}
+
+/// Commit at the head of `branch` on the remote
+pub fn fetch_remote_version(
+    username: &str,
+    folder_name: &str,
+    branch: &str,
+) -> Result<String, String> {
+    match execute_commande(&format!(
+        "git ls-remote git@github.com:{}/{}.git {:?}",
+        username, folder_name, branch
+    )) {
+        Ok(v) => return Ok(v.trim().split("refs").next().unwrap().trim().to_string()),
+        Err(err) => return Err(err),
+    }
+}
+
+/// Commit a sha, branch or tag points to on the remote
+pub fn resolve_ref(username: &str, folder_name: &str, git_ref: &str) -> Result<String, String> {
+    // refs come from the command line and the HTTP API, quoted so they stay a single word
+    let output = execute_commande(&format!(
+        "git ls-remote git@github.com:{}/{}.git {} {}",
+        username,
+        folder_name,
+        shell_quote(git_ref),
+        shell_quote(&format!("{}^{{}}", git_ref))
+    ))?;
+
+    let refs: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect();
+
+    // annotated tags point to the tag object, the peeled `^{}` line holds the commit
+    if let Some((sha, _)) = refs.iter().find(|(_, name)| name.ends_with("^{}")) {
+        return Ok(sha.to_string());
+    }
+    if let Some((sha, _)) = refs.first() {
+        return Ok(sha.to_string());
+    }
 
+    let is_sha = git_ref.len() >= 7 && git_ref.chars().all(|c| c.is_ascii_hexdigit());
+    match is_sha {
+        true => return Ok(git_ref.to_lowercase()),
+        false => {
+            return Err(format!(
+                "no branch, tag or commit named {} on the remote",
+                git_ref
+            ));
+        }
+    }
+}
+
+/// Clones the repository in `work_dir`, checks out `commit` and returns its full sha
+pub fn clone_at(
+    work_dir: &str,
+    username: &str,
+    folder_name: &str,
+    commit: &str,
+) -> Result<String, String> {
+    return execute_commande(&format!(
+        "cd {} && git clone --quiet git@github.com:{}/{}.git && cd {} && git checkout --quiet {} && git rev-parse HEAD",
+        work_dir, username, folder_name, folder_name, commit
+    ))
+    .map(|sha| sha.trim().to_string());
+}
+
+/// Deploy metadata exposed to every step, hook and template of a run,
+/// the release path lists the deploy destinations separated by `:`
+pub fn deploy_metadata(
+    commit: &str,
+    branch: &str,
+    repo_dir: &str,
+    mouve: &[FromTo],
+    run_id: &str,
+) -> HashMap<String, String> {
+    let mut destinations: Vec<&str> = Vec::new();
+    for command in mouve {
+        if !destinations.contains(&command.to.as_str()) {
+            destinations.push(&command.to);
+        }
+    }
+    let release_path = destinations.join(":");
+
+    return [
+        ("FLOW_COMMIT_SHA", commit.trim()),
+        ("FLOW_BRANCH", branch),
+        ("FLOW_BUILD_DIR", repo_dir),
+        ("FLOW_RELEASE_PATH", &release_path),
+        ("FLOW_RUN_ID", run_id),
+    ]
+    .into_iter()
+    .map(|(key, val)| (key.to_string(), val.to_string()))
+    .collect();
+}
+
+/// The artifact store is keyed by commit and by everything that shapes the build output
+pub fn artifacts_cache_key(config: &ConfigFile, commit: &str) -> String {
+    let sources: Vec<(&String, &Option<Vec<String>>)> =
+        config.mouve.iter().map(|m| (&m.from, &m.exclude)).collect();
+
+    return cache_key(
+        commit,
+        &serde_json::to_string(&(&config.pre_build, &config.build, &sources, &config.env))
+            .unwrap_or_default(),
+    );
+}
+
+/// Variables available to templates, the deploy metadata wins over env, vars and secrets
+pub fn template_vars(
+    config: &ConfigFile,
+    metadata: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, String> {
+    let mut vars = config.env.clone().unwrap_or_default();
+    vars.extend(config.vars.clone().unwrap_or_default());
+
+    if let Some(path) = &config.secrets_file {
+        match load_env_file(path) {
+            Ok(secrets) => vars.extend(secrets),
+            Err(err) => return Err(format!("secrets file {} : {}", path, err)),
+        }
+    }
+
+    vars.extend(metadata.clone());
+
+    return Ok(vars);
+}
+
+/// Renders every template in memory, failing on the first template with a missing variable
+pub fn render_templates(
+    templates: &[TemplateConfig],
+    repo_dir: &str,
+    vars: &HashMap<String, String>,
+) -> Result<Vec<(TemplateConfig, String, Ownership)>, String> {
+    let mut rendered = Vec::<(TemplateConfig, String, Ownership)>::new();
+
+    if !templates.is_empty() {
+        info!(stage = "templates"; "Rendering templates");
+    }
+
+    for template in templates {
+        let res = read_from_file_ut(&format!("{}/{}", repo_dir, &template.from))
+            .and_then(|content| render_template(&content, vars))
+            .and_then(|content| {
+                let ownership =
+                    resolve_ids(&template.owner, &template.group, &template.file_mode, &None)?;
+                Ok((content, ownership))
+            });
+
+        match res {
+            Ok((content, ownership)) => rendered.push((template.clone(), content, ownership)),
+            Err(err) => return Err(format!("{} : {}", &template.from, err)),
+        }
+    }
+
+    return Ok(rendered);
+}
+
+/// Runner of the files with `extention` and the args printing its version
+pub fn runner_for_extension(extention: &str) -> Option<(&'static str, &'static [&'static str; 1])> {
     // (file-extension, (runner, args-to-print-version))
     let runners: HashMap<&'static str, (&'static str, &'static [&'static str; 1])> = [
         ("js", ("node", &["--version"])),    // Node.js
@@ -258,7 +761,14 @@ pub fn check_existing_runner(extention: &str) -> Result<&str, String> {
     .into_iter()
     .collect();
 
-    let (cmd, args) = runners[extention];
+    return runners.get(extention).copied();
+}
+
+pub fn check_existing_runner(extention: &str) -> Result<&str, String> {
+    let (cmd, args) = match runner_for_extension(extention) {
+        Some(runner) => runner,
+        None => return Err(format!("✖ no known runner for .{} files", &extention)),
+    };
 
     // for (ext, (cmd, args)) in &runners {
     match Command::new(cmd).args(*args).status() {
@@ -307,15 +817,13 @@ pub fn get_process_runner(entry_point: &str) -> Result<String, String> {
     };
 }
 
-pub fn run_binary() -> () {}
-
 pub fn check_or_create_entry_point(
     config_file_path: &str,
     config: &mut ConfigFile,
     name: &str,
 ) -> Result<bool, String> {
     for target in config.mouve.clone() {
-        let FromTo { from: _, mut to } = target;
+        let FromTo { mut to, .. } = target;
 
         if config.entry_point.is_none() {
             config.entry_point = Some(Vec::new());
@@ -341,7 +849,7 @@ pub fn check_or_create_entry_point(
                 Ok(res) => Some(res),
                 Err(err) => {
                     println!("{}", err);
-                    Some(format!(""))
+                    Some(String::new())
                 }
             };
             let unwraped_entry = entry.unwrap();
@@ -385,8 +893,8 @@ pub fn check_or_create_entry_point(
     match execute_commande(&format!("rm {}", &config_file_path)) {
         Ok(_) => {
             let _ = write_to_file_ut(
-                &config_file_path,
-                &serde_json::to_string_pretty::<ConfigFile>(&config).unwrap(),
+                config_file_path,
+                &serde_json::to_string_pretty::<ConfigFile>(config).unwrap(),
             );
             return Ok(true);
         }
@@ -396,7 +904,7 @@ pub fn check_or_create_entry_point(
     }
 }
 
-fn load_env_file(path: &str) -> Result<HashMap<String, String>, String> {
+pub fn load_env_file(path: &str) -> Result<HashMap<String, String>, String> {
     let mut vars = HashMap::new();
 
     let contents = match fs::read_to_string(path) {
@@ -407,7 +915,7 @@ fn load_env_file(path: &str) -> Result<HashMap<String, String>, String> {
     };
 
     for line in contents.lines() {
-        if line.starts_with('#') && !line.contains('=') {
+        if line.trim_start().starts_with('#') {
             continue;
         }
         let parts: Vec<_> = line.splitn(2, '=').collect();
@@ -0,0 +1,447 @@
+use std::{collections::HashMap, fmt, process::Command};
+
+use serde_json::Value;
+
+use crate::core::utils::{
+    command::execute_commande, git::extract_repo_info, permissions::parse_mode,
+    remote::parse_target,
+};
+
+use super::{
+    stages::resolve_needs,
+    structs::{BuildStep, ConfigFile, NotifierConfig, NotifierKind},
+    utils::runner_for_extension,
+};
+
+const CONFIG_FIELDS: &[&str] = &[
+    "repo",
+    "build",
+    "mouve",
+    "pre_build",
+    "pre_deploy",
+    "post_deploy",
+    "on_failure",
+    "max_parallel",
+    "cache",
+    "logs",
+    "notify",
+    "env",
+    "vars",
+    "secrets_file",
+    "templates",
+    "hosts",
+    "branch",
+    "version",
+    "entry_point",
+];
+const STAGES: &[&str] = &[
+    "pre_build",
+    "build",
+    "pre_deploy",
+    "post_deploy",
+    "on_failure",
+];
+const STEP_FIELDS: &[&str] = &[
+    "name",
+    "run",
+    "needs",
+    "timeout",
+    "cwd",
+    "env",
+    "continue_on_error",
+    "shell",
+    "host",
+];
+const MOUVE_FIELDS: &[&str] = &[
+    "from",
+    "to",
+    "exclude",
+    "mode",
+    "compare",
+    "owner",
+    "group",
+    "file_mode",
+    "dir_mode",
+];
+const TEMPLATE_FIELDS: &[&str] = &["from", "to", "owner", "group", "file_mode"];
+const HOST_FIELDS: &[&str] = &["host", "user", "port", "identity_file", "options"];
+const CACHE_FIELDS: &[&str] = &["enabled", "max_size_mb", "max_age_days"];
+const LOGS_FIELDS: &[&str] = &["max_size_mb", "max_age_days", "keep", "compress", "format"];
+const NOTIFY_FIELDS: &[&str] = &[
+    "type",
+    "events",
+    "url",
+    "body",
+    "headers",
+    "smtp",
+    "username",
+    "password",
+    "from",
+    "to",
+    "command",
+    "retries",
+    "retry_delay",
+];
+
+/// A problem found in a config file, with its position when it can be located
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "line {}, column {}: {}", line, column, self.message)
+            }
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+fn diagnostic(content: &str, needle: Option<&str>, message: String) -> Diagnostic {
+    let (line, column) = match needle.and_then(|needle| locate(content, needle)) {
+        Some((line, column)) => (Some(line), Some(column)),
+        None => (None, None),
+    };
+
+    return Diagnostic {
+        line,
+        column,
+        message,
+    };
+}
+
+/// Line and column of the first occurrence of `needle`
+fn locate(content: &str, needle: &str) -> Option<(usize, usize)> {
+    let offset = content.find(needle)?;
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map(|idx| idx + 1).unwrap_or(0) + 1;
+
+    return Some((line, column));
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr.push((prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+
+    return prev[b.len()];
+}
+
+fn check_fields(
+    content: &str,
+    value: &Value,
+    known: &[&str],
+    place: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> () {
+    let object = match value.as_object() {
+        Some(object) => object,
+        None => return,
+    };
+
+    for key in object.keys() {
+        if known.contains(&key.as_str()) {
+            continue;
+        }
+
+        let suggestion = known
+            .iter()
+            .map(|field| (edit_distance(key, field), field))
+            .filter(|(distance, _)| *distance <= 2)
+            .min_by_key(|(distance, _)| *distance);
+
+        let message = match suggestion {
+            Some((_, field)) => format!(
+                "unknown field `{}` in {}, did you mean `{}`?",
+                key, place, field
+            ),
+            None => format!("unknown field `{}` in {}", key, place),
+        };
+
+        diagnostics.push(diagnostic(content, Some(&format!("\"{}\"", key)), message));
+    }
+}
+
+fn check_unknown_fields(content: &str, root: &Value, diagnostics: &mut Vec<Diagnostic>) -> () {
+    check_fields(content, root, CONFIG_FIELDS, "the config", diagnostics);
+
+    for stage in STAGES {
+        for step in root[*stage].as_array().into_iter().flatten() {
+            check_fields(
+                content,
+                step,
+                STEP_FIELDS,
+                &format!("a {} step", stage),
+                diagnostics,
+            );
+        }
+    }
+    for entry in root["mouve"].as_array().into_iter().flatten() {
+        check_fields(content, entry, MOUVE_FIELDS, "a mouve entry", diagnostics);
+    }
+    for template in root["templates"].as_array().into_iter().flatten() {
+        check_fields(
+            content,
+            template,
+            TEMPLATE_FIELDS,
+            "a template",
+            diagnostics,
+        );
+    }
+    for (name, host) in root["hosts"].as_object().into_iter().flatten() {
+        check_fields(
+            content,
+            host,
+            HOST_FIELDS,
+            &format!("host {}", name),
+            diagnostics,
+        );
+    }
+    check_fields(content, &root["cache"], CACHE_FIELDS, "cache", diagnostics);
+    check_fields(content, &root["logs"], LOGS_FIELDS, "logs", diagnostics);
+    for notifier in root["notify"].as_array().into_iter().flatten() {
+        check_fields(content, notifier, NOTIFY_FIELDS, "a notifier", diagnostics);
+    }
+}
+
+fn check_runner(entry: &str) -> Result<(), String> {
+    let file = entry.split('/').next_back().unwrap_or(entry);
+
+    // files without an extension are executed directly
+    let extension = match file.rsplit_once('.') {
+        Some((_, extension)) => extension,
+        None => return Ok(()),
+    };
+
+    let (cmd, args) = match runner_for_extension(extension) {
+        Some(runner) => runner,
+        None => return Err(format!("no known runner for .{} files", extension)),
+    };
+
+    match Command::new(cmd).args(args).output() {
+        Ok(output) if output.status.success() => return Ok(()),
+        _ => {
+            return Err(format!(
+                "`{}` is not installed, it is needed to run .{} files",
+                cmd, extension
+            ));
+        }
+    }
+}
+
+/// The fields a notifier of its type can't go without
+fn check_notifier(notifier: &NotifierConfig) -> Result<(), String> {
+    let missing = |field: &str| {
+        format!(
+            "a {} notifier needs `{}`",
+            serde_json::to_value(notifier.kind)
+                .unwrap()
+                .as_str()
+                .unwrap_or(""),
+            field
+        )
+    };
+
+    match notifier.kind {
+        NotifierKind::Webhook | NotifierKind::Slack if notifier.url.is_none() => {
+            return Err(missing("url"));
+        }
+        NotifierKind::Email if notifier.smtp.is_none() => return Err(missing("smtp")),
+        NotifierKind::Email if notifier.from.is_none() => return Err(missing("from")),
+        NotifierKind::Email if notifier.to.as_ref().is_none_or(|to| to.is_empty()) => {
+            return Err(missing("to"));
+        }
+        NotifierKind::Command if notifier.command.is_none() => return Err(missing("command")),
+        _ => return Ok(()),
+    }
+}
+
+fn check_config(
+    content: &str,
+    config: &ConfigFile,
+    online: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> () {
+    let repo_needle = format!("{:?}", &config.repo);
+    let repo_info = extract_repo_info(&config.repo);
+
+    if repo_info.is_none() {
+        diagnostics.push(diagnostic(
+            content,
+            Some(&repo_needle),
+            format!(
+                "repo `{}` is not a repository url such as https://github.com/user/repo.git",
+                &config.repo
+            ),
+        ));
+    }
+
+    let hosts = config.hosts.clone().unwrap_or_default();
+
+    for entry in &config.mouve {
+        let needle = format!("{:?}", &entry.to);
+
+        if parse_target(&entry.to, &hosts).is_none() && !entry.to.starts_with('/') {
+            diagnostics.push(diagnostic(
+                content,
+                Some(&needle),
+                format!("mouve destination `{}` must be an absolute path", &entry.to),
+            ));
+        }
+        for (label, mode) in [
+            ("file_mode", &entry.file_mode),
+            ("dir_mode", &entry.dir_mode),
+        ] {
+            if let Some(mode) = mode
+                && let Err(err) = parse_mode(mode)
+            {
+                diagnostics.push(diagnostic(
+                    content,
+                    Some(&format!("{:?}", mode)),
+                    format!("{} of `{}` : {}", label, &entry.to, err),
+                ));
+            }
+        }
+    }
+
+    for template in config.templates.clone().unwrap_or_default() {
+        if !template.to.starts_with('/') {
+            diagnostics.push(diagnostic(
+                content,
+                Some(&format!("{:?}", &template.to)),
+                format!(
+                    "template destination `{}` must be an absolute path",
+                    &template.to
+                ),
+            ));
+        }
+    }
+
+    for notifier in config.notify.clone().unwrap_or_default() {
+        if let Err(err) = check_notifier(&notifier) {
+            diagnostics.push(diagnostic(content, Some("\"notify\""), err));
+        }
+    }
+
+    let stages: HashMap<&str, Vec<BuildStep>> = [
+        ("pre_build", config.pre_build.clone().unwrap_or_default()),
+        ("build", config.build.clone()),
+        ("pre_deploy", config.pre_deploy.clone().unwrap_or_default()),
+        (
+            "post_deploy",
+            config.post_deploy.clone().unwrap_or_default(),
+        ),
+        ("on_failure", config.on_failure.clone().unwrap_or_default()),
+    ]
+    .into_iter()
+    .collect();
+
+    for stage in STAGES {
+        let steps: Vec<_> = stages[stage].iter().map(|step| step.to_step()).collect();
+        if let Err(err) = resolve_needs(&steps) {
+            diagnostics.push(diagnostic(
+                content,
+                Some(&format!("\"{}\"", stage)),
+                format!("{} : {}", stage, err),
+            ));
+        }
+    }
+
+    for entry in config
+        .entry_point
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+    {
+        if let Err(err) = check_runner(&entry) {
+            diagnostics.push(diagnostic(
+                content,
+                Some(&format!("{:?}", &entry)),
+                format!("entry point `{}` : {}", &entry, err),
+            ));
+        }
+    }
+
+    if !online {
+        return;
+    }
+
+    if let Some((username, folder_name)) = repo_info {
+        let branch = config.branch.clone().unwrap_or("main".to_string());
+
+        match execute_commande(&format!(
+            "git ls-remote --heads git@github.com:{}/{}.git {:?}",
+            username, folder_name, branch
+        )) {
+            Ok(heads) if heads.trim().is_empty() => diagnostics.push(diagnostic(
+                content,
+                Some(&format!("{:?}", &branch)),
+                format!("branch `{}` does not exist on the remote", &branch),
+            )),
+            Ok(_) => {}
+            Err(err) => diagnostics.push(diagnostic(
+                content,
+                Some(&repo_needle),
+                format!("could not reach the repository : {}", err.trim()),
+            )),
+        }
+    }
+}
+
+/// Checks a config file's content, `online` also checks the remote branch. The config is
+/// only given back when it is free of problems
+pub fn validate_config(content: &str, online: bool) -> Result<ConfigFile, Vec<Diagnostic>> {
+    let json_error = |err: serde_json::Error| {
+        let message = err.to_string();
+        let position = format!(" at line {} column {}", err.line(), err.column());
+
+        return Diagnostic {
+            line: Some(err.line()),
+            column: Some(err.column()),
+            message: message
+                .strip_suffix(&position)
+                .unwrap_or(&message)
+                .to_string(),
+        };
+    };
+
+    let root = match serde_json::from_str::<Value>(content) {
+        Ok(root) => root,
+        Err(err) => return Err(vec![json_error(err)]),
+    };
+
+    let mut diagnostics = Vec::<Diagnostic>::new();
+
+    check_unknown_fields(content, &root, &mut diagnostics);
+
+    let config = match serde_json::from_str::<ConfigFile>(content) {
+        Ok(config) => {
+            check_config(content, &config, online, &mut diagnostics);
+            config
+        }
+        Err(err) => {
+            diagnostics.push(json_error(err));
+            return Err(diagnostics);
+        }
+    };
+
+    match diagnostics.len() {
+        0 => return Ok(config),
+        _ => return Err(diagnostics),
+    }
+}